@@ -1,111 +1,407 @@
-use super::utils::read_lines;
+use super::utils::parse_number;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Lines are handed to the workers in chunks of roughly this many bytes
+const CHUNK_BYTES: usize = 1 << 20;
 
 #[derive(Debug, Eq, PartialEq)]
 struct Entry<'a> {
   lowest: usize,
   highest: usize,
-  letter: char,
+  letter: &'a str,
   password: &'a str,
 }
 
 impl Entry<'_> {
+  fn letter_char(&self) -> Option<char> {
+    self.letter.chars().next()
+  }
+
   fn valid(&self) -> bool {
-    let count = self.password.matches(self.letter).count();
+    let letter = self.letter_char();
+    let count = self.password.chars().filter(|&c| Some(c) == letter).count();
     count >= self.lowest && count <= self.highest
   }
 
   fn valid_second(&self) -> bool {
-    let first = parse_char_at(self.password, self.lowest - 1);
-    let second = parse_char_at(self.password, self.highest - 1);
+    let first = parse_char_at(self.password, self.lowest.wrapping_sub(1));
+    let second = parse_char_at(self.password, self.highest.wrapping_sub(1));
+
+    (first.is_some() && first == self.letter_char())
+      != (second.is_some() && second == self.letter_char())
+  }
+
+  fn valid_with(&self, matching: &Matching) -> bool {
+    match matching {
+      Matching::Chars => self.valid(),
+      Matching::Graphemes(graphemes) => graphemes.valid(self),
+    }
+  }
+
+  fn valid_second_with(&self, matching: &Matching) -> bool {
+    match matching {
+      Matching::Chars => self.valid_second(),
+      Matching::Graphemes(graphemes) => graphemes.valid_second(self),
+    }
+  }
+}
+
+// How the policy letter is compared against the password
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Matching {
+  // Positions and counts are in chars, as the puzzle defines them
+  #[default]
+  Chars,
+  // Positions and counts are in extended grapheme clusters
+  Graphemes(GraphemeMatch),
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct GraphemeMatch {
+  ignore_case: bool,
+  // Compare graphemes by their NFKC form, so composed and decomposed
+  // accents or compatibility forms are considered the same letter
+  normalize: bool,
+}
+
+impl GraphemeMatch {
+  fn key(&self, grapheme: &str) -> String {
+    let key: String = if self.normalize {
+      grapheme.nfkc().collect()
+    } else {
+      grapheme.to_string()
+    };
+    if self.ignore_case {
+      key.to_lowercase()
+    } else {
+      key
+    }
+  }
+
+  fn letter_key(&self, entry: &Entry) -> String {
+    self.key(entry.letter.graphemes(true).next().unwrap_or_default())
+  }
+
+  fn valid(&self, entry: &Entry) -> bool {
+    let letter = self.letter_key(entry);
+    let count = entry
+      .password
+      .graphemes(true)
+      .filter(|g| self.key(g) == letter)
+      .count();
+    count >= entry.lowest && count <= entry.highest
+  }
 
-    (first == self.letter && second != self.letter)
-      || (first != self.letter && second == self.letter)
+  fn valid_second(&self, entry: &Entry) -> bool {
+    let letter = self.letter_key(entry);
+    let matches_at = |pos: usize| {
+      pos
+        .checked_sub(1)
+        .and_then(|pos| entry.password.graphemes(true).nth(pos))
+        .is_some_and(|g| self.key(g) == letter)
+    };
+
+    matches_at(entry.lowest) != matches_at(entry.highest)
   }
 }
 
-pub fn password_policy() -> (i32, i32, i32) {
-  let mut valid_count = 0;
-  let mut valid_second_count = 0;
-  let mut count = 0;
-  if let Ok(lines) = read_lines("inputs/day2.txt") {
-    for line in lines {
-      count += 1;
-      if let Ok(l) = line {
-        let entry = parse_line(l.as_str());
-        if entry.valid() {
-          valid_count += 1
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Tally {
+  pub count: u64,
+  pub valid: u64,
+  pub valid_second: u64,
+  pub malformed: u64,
+}
+
+impl Tally {
+  fn add_line(&mut self, line: &[u8], matching: &Matching) {
+    let line = match std::str::from_utf8(line) {
+      Ok(l) => l.trim(),
+      Err(_) => {
+        self.count += 1;
+        self.malformed += 1;
+        return;
+      }
+    };
+    if line.is_empty() {
+      return;
+    }
+    self.count += 1;
+    match parse_line(line) {
+      Ok(entry) => {
+        if entry.valid_with(matching) {
+          self.valid += 1;
         }
-        if entry.valid_second() {
-          valid_second_count += 1
+        if entry.valid_second_with(matching) {
+          self.valid_second += 1;
         }
       }
+      Err(_) => self.malformed += 1,
+    }
+  }
+
+  fn merge(&mut self, other: &Tally) {
+    self.count += other.count;
+    self.valid += other.valid;
+    self.valid_second += other.valid_second;
+    self.malformed += other.malformed;
+  }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Options {
+  // None reads from stdin
+  input: Option<PathBuf>,
+  progress: Option<u64>,
+  jobs: usize,
+  matching: Matching,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      input: Some(PathBuf::from("inputs/day2.txt")),
+      progress: None,
+      jobs: 1,
+      matching: Matching::Chars,
+    }
+  }
+}
+
+impl Options {
+  pub fn from_args(args: &[String]) -> Result<Self, Error> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| Error::Usage(format!("{} expects a value", arg)))
+      };
+      match arg.as_str() {
+        "--input" => {
+          options.input = match value()?.as_str() {
+            "-" => None,
+            path => Some(PathBuf::from(path)),
+          }
+        }
+        "--progress" => options.progress = Some(parse_count(arg, value()?)?),
+        "--jobs" => options.jobs = parse_count(arg, value()?)? as usize,
+        "--graphemes" => {
+          options.graphemes();
+        }
+        "--ignore-case" => options.graphemes().ignore_case = true,
+        "--normalize" => options.graphemes().normalize = true,
+        _ => return Err(Error::Usage(format!("unknown argument {}", arg))),
+      }
+    }
+    Ok(options)
+  }
+
+  fn graphemes(&mut self) -> &mut GraphemeMatch {
+    if self.matching == Matching::Chars {
+      self.matching = Matching::Graphemes(GraphemeMatch::default());
+    }
+    match &mut self.matching {
+      Matching::Graphemes(graphemes) => graphemes,
+      Matching::Chars => unreachable!(),
     }
   }
-  (valid_count, valid_second_count, count)
 }
 
-fn parse_usize(str_val: &str) -> usize {
-  match str_val.parse::<usize>() {
-    Ok(value) => value,
-    _ => panic!(format!("Illegal value: [{}]", str_val)),
+fn parse_count(arg: &str, value: &str) -> Result<u64, Error> {
+  match parse_number(arg, value).map_err(Error::Usage)? {
+    0 => Err(Error::Usage(format!("{} must be positive", arg))),
+    count => Ok(count),
   }
 }
 
-fn parse_char(str_val: &str) -> char {
-  match str_val.chars().nth(0) {
-    Some(c) => c,
-    _ => panic!(format!("Illegal char: [{}]", str_val)),
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  #[error("{0}")]
+  Usage(String),
+}
+
+pub fn password_policy(options: &Options) -> Result<Tally, Error> {
+  let reader: Box<dyn BufRead> = match &options.input {
+    Some(path) => Box::new(BufReader::new(File::open(path)?)),
+    None => Box::new(BufReader::new(io::stdin())),
+  };
+  let tally = if options.jobs > 1 {
+    validate_parallel(reader, options.jobs, options.progress, &options.matching)?
+  } else {
+    validate_stream(reader, options.progress, &options.matching)?
+  };
+  Ok(tally)
+}
+
+fn report_progress(lines: u64, progress: Option<u64>) {
+  if let Some(every) = progress {
+    if lines.is_multiple_of(every) {
+      eprintln!("{} lines processed", lines);
+    }
   }
 }
 
-fn parse_char_at(str_val: &str, pos: usize) -> char {
-  match str_val.chars().nth(pos) {
-    Some(c) => c,
-    _ => panic!(format!("Illegal char: [{}]", str_val)),
+// Validates one line at a time, only ever holding the current line in memory
+fn validate_stream<R: BufRead>(
+  mut reader: R,
+  progress: Option<u64>,
+  matching: &Matching,
+) -> io::Result<Tally> {
+  let mut tally = Tally::default();
+  let mut buf = Vec::new();
+  let mut lines = 0;
+  while reader.read_until(b'\n', &mut buf)? != 0 {
+    tally.add_line(&buf, matching);
+    buf.clear();
+    lines += 1;
+    report_progress(lines, progress);
   }
+  Ok(tally)
 }
 
-fn parse_line(policy: &str) -> Entry {
-  let first_part: Vec<&str> = policy.split(":").collect();
-  let second_part: Vec<&str> = policy.split(" ").collect();
-  let min_max: Vec<&str> = second_part[0].split("-").collect();
+// Reads chunks of whole lines and lets `jobs` workers validate them. The
+// channel is bounded so at most a few chunks per worker are held in memory.
+fn validate_parallel<R: BufRead>(
+  mut reader: R,
+  jobs: usize,
+  progress: Option<u64>,
+  matching: &Matching,
+) -> io::Result<Tally> {
+  let (sender, receiver) = sync_channel::<Vec<u8>>(jobs * 2);
+  let receiver = Arc::new(Mutex::new(receiver));
+
+  thread::scope(|scope| {
+    let workers: Vec<_> = (0..jobs)
+      .map(|_| {
+        let receiver = Arc::clone(&receiver);
+        scope.spawn(move || {
+          let mut tally = Tally::default();
+          loop {
+            let chunk = match receiver.lock().unwrap().recv() {
+              Ok(chunk) => chunk,
+              Err(_) => break,
+            };
+            for line in chunk.split(|&b| b == b'\n') {
+              tally.add_line(line, matching);
+            }
+          }
+          tally
+        })
+      })
+      .collect();
+    // Only the workers hold the receiver now, so sending fails rather than
+    // blocks once every worker is gone
+    drop(receiver);
 
-  Entry {
-    lowest: parse_usize(min_max[0]),
-    highest: parse_usize(min_max[1]),
-    letter: parse_char(second_part[1]),
-    password: first_part[1].trim(),
+    let mut lines = 0;
+    let read = (|| {
+      loop {
+        let mut chunk = Vec::with_capacity(CHUNK_BYTES);
+        while chunk.len() < CHUNK_BYTES {
+          if reader.read_until(b'\n', &mut chunk)? == 0 {
+            break;
+          }
+          lines += 1;
+          report_progress(lines, progress);
+        }
+        if chunk.is_empty() {
+          return Ok(());
+        }
+        // Workers only stop early by panicking, which is reported on join
+        if sender.send(chunk).is_err() {
+          return Ok(());
+        }
+      }
+    })();
+    drop(sender);
+
+    let mut tally = Tally::default();
+    for worker in workers {
+      tally.merge(&worker.join().expect("validation worker panicked"));
+    }
+    read.map(|_| tally)
+  })
+}
+
+fn parse_usize(str_val: &str) -> Result<usize, String> {
+  str_val
+    .trim()
+    .parse::<usize>()
+    .map_err(|_| format!("Illegal value: [{}]", str_val))
+}
+
+fn parse_letter(str_val: &str) -> Result<&str, String> {
+  match str_val.trim() {
+    "" => Err(format!("Illegal letter: [{}]", str_val)),
+    letter => Ok(letter),
   }
 }
 
+fn parse_char_at(str_val: &str, pos: usize) -> Option<char> {
+  str_val.chars().nth(pos)
+}
+
+fn parse_line(policy: &str) -> Result<Entry<'_>, String> {
+  let mut first_part = policy.splitn(2, ':');
+  let rule = first_part.next().unwrap_or_default();
+  let password = first_part
+    .next()
+    .ok_or_else(|| format!("Missing password: [{}]", policy))?;
+  let mut second_part = rule.split_whitespace();
+  let min_max = second_part.next().unwrap_or_default();
+  let letter = second_part
+    .next()
+    .ok_or_else(|| format!("Missing letter: [{}]", policy))?;
+  let mut min_max = min_max.splitn(2, '-');
+
+  Ok(Entry {
+    lowest: parse_usize(min_max.next().unwrap_or_default())?,
+    highest: parse_usize(min_max.next().unwrap_or_default())?,
+    letter: parse_letter(letter)?,
+    password: password.trim(),
+  })
+}
+
 #[test]
 fn parse_line_expected() {
   assert_eq!(
     parse_line("1-3 a: abcde"),
-    Entry {
+    Ok(Entry {
       lowest: 1,
       highest: 3,
-      letter: 'a',
+      letter: "a",
       password: "abcde"
-    }
+    })
   );
   assert_eq!(
     parse_line("1-3 b: cdefg"),
-    Entry {
+    Ok(Entry {
       lowest: 1,
       highest: 3,
-      letter: 'b',
+      letter: "b",
       password: "cdefg"
-    }
+    })
   );
   assert_eq!(
     parse_line("2-9 c: ccccccccc"),
-    Entry {
+    Ok(Entry {
       lowest: 2,
       highest: 9,
-      letter: 'c',
+      letter: "c",
       password: "ccccccccc"
-    }
+    })
   );
 }
 
@@ -114,21 +410,21 @@ fn validates_expected() {
   let entry1 = Entry {
     lowest: 1,
     highest: 3,
-    letter: 'a',
+    letter: "a",
     password: "abcde",
   };
   assert_eq!(entry1.valid(), true);
   let entry2 = Entry {
     lowest: 1,
     highest: 3,
-    letter: 'b',
+    letter: "b",
     password: "cdefg",
   };
   assert_eq!(entry2.valid(), false);
   let entry3 = Entry {
     lowest: 2,
     highest: 9,
-    letter: 'c',
+    letter: "c",
     password: "ccccccccc",
   };
   assert_eq!(entry3.valid(), true);
@@ -139,22 +435,86 @@ fn validate_second_expected() {
   let entry1 = Entry {
     lowest: 1,
     highest: 3,
-    letter: 'a',
+    letter: "a",
     password: "abcde",
   };
   assert_eq!(entry1.valid_second(), true);
   let entry2 = Entry {
     lowest: 1,
     highest: 3,
-    letter: 'b',
+    letter: "b",
     password: "cdefg",
   };
   assert_eq!(entry2.valid_second(), false);
   let entry3 = Entry {
     lowest: 2,
     highest: 9,
-    letter: 'c',
+    letter: "c",
     password: "ccccccccc",
   };
   assert_eq!(entry3.valid_second(), false);
 }
+
+#[test]
+fn validate_stream_expected() {
+  let input = "1-3 a: abcde\n1-3 b: cdefg\n\n2-9 c: ccccccccc\nnot a policy\n";
+  let tally = validate_stream(io::Cursor::new(input), None, &Matching::Chars).unwrap();
+  assert_eq!(
+    tally,
+    Tally {
+      count: 4,
+      valid: 2,
+      valid_second: 1,
+      malformed: 1
+    }
+  );
+}
+
+#[test]
+fn validate_parallel_matches_stream() {
+  let input: String = (0..5000)
+    .map(|i| format!("{}-{} a: {}\n", i % 3 + 1, i % 5 + 3, "ab".repeat(i % 4)))
+    .collect();
+  let sequential = validate_stream(io::Cursor::new(&input), None, &Matching::Chars).unwrap();
+  let parallel = validate_parallel(io::Cursor::new(&input), 4, None, &Matching::Chars).unwrap();
+  assert_eq!(sequential.count, 5000);
+  assert_eq!(parallel, sequential);
+}
+
+#[test]
+fn validate_graphemes_expected() {
+  let entry = parse_line("1-1 é: cafe\u{301}").unwrap();
+  assert!(!entry.valid());
+  assert!(!entry.valid_with(&Matching::Graphemes(GraphemeMatch::default())));
+  let normalized = GraphemeMatch {
+    normalize: true,
+    ..GraphemeMatch::default()
+  };
+  assert!(entry.valid_with(&Matching::Graphemes(normalized)));
+
+  let entry = parse_line("1-2 👩: 👨\u{200d}👩\u{200d}👧👩").unwrap();
+  assert!(!entry.valid_second());
+  assert!(entry.valid_second_with(&Matching::Graphemes(GraphemeMatch::default())));
+}
+
+#[test]
+fn validate_unicode_database_expected() {
+  let tally = |matching| {
+    let file = File::open("inputs/day2_unicode_test.txt").unwrap();
+    let tally = validate_stream(BufReader::new(file), None, &matching).unwrap();
+    assert_eq!(tally.count, 5);
+    assert_eq!(tally.malformed, 0);
+    (tally.valid, tally.valid_second)
+  };
+  let graphemes = |ignore_case, normalize| {
+    Matching::Graphemes(GraphemeMatch {
+      ignore_case,
+      normalize,
+    })
+  };
+  assert_eq!(tally(Matching::Chars), (1, 0));
+  assert_eq!(tally(graphemes(false, false)), (1, 1));
+  assert_eq!(tally(graphemes(true, false)), (2, 2));
+  assert_eq!(tally(graphemes(false, true)), (3, 2));
+  assert_eq!(tally(graphemes(true, true)), (4, 3));
+}
@@ -1,76 +1,1606 @@
-use super::utils::read_lines;
-use std::path::Path;
+use super::utils::{parse_number, read_lines};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
 
-fn split_to_vec(row: String) -> Vec<char> {
-  row.chars().collect::<Vec<char>>()
+const PUZZLE_SLOPES: [Slope; 5] = [
+  Slope { right: 1, down: 1 },
+  Slope { right: 3, down: 1 },
+  Slope { right: 5, down: 1 },
+  Slope { right: 7, down: 1 },
+  Slope { right: 1, down: 2 },
+];
+
+// Moves `right` columns (negative to go left) for every `down` rows
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Slope {
+  pub right: isize,
+  pub down: usize,
+}
+
+impl FromStr for Slope {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut pair = s.split(',');
+    let right = pair
+      .next()
+      .ok_or_else(|| format!("slope incorrect {}", s))?
+      .trim()
+      .parse::<isize>()
+      .map_err(|e| format!("slope right incorrect {}: {}", s, e))?;
+    let down = pair
+      .next()
+      .ok_or_else(|| format!("slope incorrect {}, expected right,down", s))?
+      .trim()
+      .parse::<usize>()
+      .map_err(|e| format!("slope down incorrect {}: {}", s, e))?;
+    if pair.next().is_some() {
+      return Err(format!("slope invalid {}", s));
+    }
+    if down == 0 {
+      return Err(format!("slope down must be positive {}", s));
+    }
+    Ok(Slope { right, down })
+  }
+}
+
+impl fmt::Display for Slope {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{},{}", self.right, self.down)
+  }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Terrain {
+  Open,
+  Tree,
+  Rock,
+  Snow,
+  Ice,
+}
+
+impl Terrain {
+  const ALL: [Terrain; 5] = [
+    Terrain::Open,
+    Terrain::Tree,
+    Terrain::Rock,
+    Terrain::Snow,
+    Terrain::Ice,
+  ];
+
+  fn symbol(self) -> char {
+    match self {
+      Terrain::Open => '.',
+      Terrain::Tree => '#',
+      Terrain::Rock => '^',
+      Terrain::Snow => '*',
+      Terrain::Ice => '~',
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Terrain::Open => "open",
+      Terrain::Tree => "tree",
+      Terrain::Rock => "rock",
+      Terrain::Snow => "snow",
+      Terrain::Ice => "ice",
+    }
+  }
+
+  fn from_symbol(symbol: char) -> Option<Terrain> {
+    Terrain::ALL.iter().copied().find(|t| t.symbol() == symbol)
+  }
+}
+
+impl FromStr for Terrain {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Terrain::ALL
+      .iter()
+      .copied()
+      .find(|t| t.name() == s.trim())
+      .ok_or_else(|| format!("unknown terrain {}", s))
+  }
+}
+
+impl fmt::Display for Terrain {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.symbol())
+  }
+}
+
+// What landing on each kind of terrain costs
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CostTable {
+  costs: [u64; 5],
+}
+
+impl Default for CostTable {
+  // Only trees count, as in the puzzle
+  fn default() -> Self {
+    let mut costs = [0; 5];
+    costs[Terrain::Tree as usize] = 1;
+    CostTable { costs }
+  }
+}
+
+impl CostTable {
+  fn cost(&self, terrain: Terrain) -> u64 {
+    self.costs[terrain as usize]
+  }
+
+  fn set(&mut self, terrain: Terrain, cost: u64) {
+    self.costs[terrain as usize] = cost;
+  }
+
+  fn counts_trees(&self) -> bool {
+    *self == CostTable::default()
+  }
+
+  // Applies a list like "tree=1,rock=5" on top of the current costs
+  fn update(&mut self, s: &str) -> Result<(), String> {
+    for pair in s.split(',') {
+      let mut pair = pair.split('=');
+      let terrain = pair
+        .next()
+        .ok_or_else(|| format!("cost incorrect {}", s))?
+        .parse::<Terrain>()?;
+      let cost = pair
+        .next()
+        .ok_or_else(|| format!("cost incorrect {}, expected terrain=cost", s))?
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("cost incorrect {}: {}", s, e))?;
+      if pair.next().is_some() {
+        return Err(format!("cost invalid {}", s));
+      }
+      self.set(terrain, cost);
+    }
+    Ok(())
+  }
+}
+
+// What a traversal does when it moves past an edge of the map
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Edge {
+  // Continue from the opposite edge, as if the map repeats
+  Wrap,
+  // Stay on the outermost row or column
+  Clamp,
+  // End the traversal
+  Stop,
+}
+
+impl FromStr for Edge {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim() {
+      "wrap" => Ok(Edge::Wrap),
+      "clamp" => Ok(Edge::Clamp),
+      "stop" => Ok(Edge::Stop),
+      _ => Err(format!(
+        "unknown edge mode {}, expected wrap, clamp or stop",
+        s
+      )),
+    }
+  }
+}
+
+impl Edge {
+  // Maps a coordinate onto 0..len, or None if the traversal ends
+  fn resolve(self, position: i128, len: usize) -> Option<usize> {
+    let len = len as i128;
+    match self {
+      Edge::Wrap => Some(position.rem_euclid(len) as usize),
+      Edge::Clamp => Some(position.clamp(0, len - 1) as usize),
+      Edge::Stop if (0..len).contains(&position) => Some(position as usize),
+      Edge::Stop => None,
+    }
+  }
 }
 
-fn read_from_file<P>(filename: P) -> Vec<Vec<char>>
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Boundary {
+  horizontal: Edge,
+  vertical: Edge,
+  // The most squares a traversal lands on, needed when it never runs off
+  // the bottom of the map
+  steps: Option<u64>,
+}
+
+impl Default for Boundary {
+  // The puzzle's map repeats to the right and ends at the bottom
+  fn default() -> Self {
+    Boundary {
+      horizontal: Edge::Wrap,
+      vertical: Edge::Stop,
+      steps: None,
+    }
+  }
+}
+
+impl Boundary {
+  fn check(&self) -> Result<(), String> {
+    if self.vertical != Edge::Stop && self.steps.is_none() {
+      return Err("--vertical wrap and clamp need a --steps budget".to_string());
+    }
+    Ok(())
+  }
+}
+
+// The squares a slope lands on, in map coordinates
+struct Traversal {
+  width: usize,
+  height: usize,
+  slope: Slope,
+  boundary: Boundary,
+  step: u64,
+}
+
+impl Traversal {
+  fn new(tree_vec: &[Vec<Terrain>], slope: Slope, boundary: Boundary) -> Traversal {
+    Traversal {
+      width: tree_vec.first().map_or(0, |row| row.len()),
+      height: tree_vec.len(),
+      slope,
+      boundary,
+      step: 0,
+    }
+  }
+}
+
+impl Iterator for Traversal {
+  type Item = (usize, usize);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.width == 0 || self.boundary.steps.is_some_and(|steps| self.step >= steps) {
+      return None;
+    }
+    let step = self.step as i128;
+    let x = self
+      .boundary
+      .horizontal
+      .resolve(step * self.slope.right as i128, self.width)?;
+    let y = self
+      .boundary
+      .vertical
+      .resolve(step * self.slope.down as i128, self.height)?;
+    self.step += 1;
+    Some((x, y))
+  }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Goal {
+  Fewest,
+  Most,
+}
+
+// Tries every slope within the bounds
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Search {
+  min_right: isize,
+  max_right: isize,
+  max_down: usize,
+  goal: Goal,
+  ranking: bool,
+}
+
+impl Default for Search {
+  fn default() -> Self {
+    Search {
+      min_right: 0,
+      max_right: 10,
+      max_down: 5,
+      goal: Goal::Fewest,
+      ranking: false,
+    }
+  }
+}
+
+// Settings for finding the cheapest way down the map
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Routing {
+  // The only top row column to start from, any of them when not given
+  start: Option<usize>,
+  moves: Vec<Slope>,
+  // Moves given with --move, which replace the defaults
+  custom_moves: bool,
+}
+
+impl Default for Routing {
+  // One row down and zero to three columns right
+  fn default() -> Self {
+    Routing {
+      start: None,
+      moves: (0..=3).map(|right| Slope { right, down: 1 }).collect(),
+      custom_moves: false,
+    }
+  }
+}
+
+impl Routing {
+  fn add_move(&mut self, step: Slope) {
+    if !self.custom_moves {
+      self.moves.clear();
+      self.custom_moves = true;
+    }
+    if !self.moves.contains(&step) {
+      self.moves.push(step);
+    }
+  }
+
+  // Adds the leftward mirror of every rightward move
+  fn allow_left(&mut self) {
+    let left: Vec<Slope> = self
+      .moves
+      .iter()
+      .filter(|step| step.right > 0)
+      .map(|step| Slope {
+        right: -step.right,
+        down: step.down,
+      })
+      .filter(|step| !self.moves.contains(step))
+      .collect();
+    self.moves.extend(left);
+  }
+}
+
+impl Search {
+  fn slopes(&self) -> Vec<Slope> {
+    (1..=self.max_down)
+      .flat_map(|down| (self.min_right..=self.max_right).map(move |right| Slope { right, down }))
+      .collect()
+  }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Options {
+  input: PathBuf,
+  slopes: Vec<Slope>,
+  search: Option<Search>,
+  route: Option<Routing>,
+  // Steps to answer with --cycle
+  cycle: Option<u64>,
+  allow_left: bool,
+  render: bool,
+  image: Option<PathBuf>,
+  scale: usize,
+  costs: CostTable,
+  boundary: Boundary,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      input: PathBuf::from("inputs/day3.txt"),
+      slopes: Vec::new(),
+      search: None,
+      route: None,
+      cycle: None,
+      allow_left: false,
+      render: false,
+      image: None,
+      scale: 1,
+      costs: CostTable::default(),
+      boundary: Boundary::default(),
+    }
+  }
+}
+
+impl Options {
+  pub fn from_args(args: &[String]) -> Result<Self, Error> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| Error::Usage(format!("{} expects a value", arg)))
+      };
+      match arg.as_str() {
+        "--input" => options.input = PathBuf::from(value()?),
+        "--slope" => options.slopes.push(value()?.parse().map_err(Error::Usage)?),
+        "--search" => {
+          options.search();
+        }
+        "--min-right" => {
+          options.search().min_right = parse_number(arg, value()?).map_err(Error::Usage)?
+        }
+        "--max-right" => {
+          options.search().max_right = parse_number(arg, value()?).map_err(Error::Usage)?
+        }
+        "--max-down" => {
+          options.search().max_down = parse_number(arg, value()?).map_err(Error::Usage)?
+        }
+        "--most" => options.search().goal = Goal::Most,
+        "--ranking" => options.search().ranking = true,
+        "--route" => {
+          options.route();
+        }
+        "--start" => {
+          options.route().start = Some(parse_number(arg, value()?).map_err(Error::Usage)?)
+        }
+        "--move" => {
+          let step = value()?.parse().map_err(Error::Usage)?;
+          options.route().add_move(step)
+        }
+        "--allow-left" => {
+          options.route();
+          options.allow_left = true;
+        }
+        "--no-wrap" => {
+          options.route();
+          options.boundary.horizontal = Edge::Stop;
+        }
+        "--cycle" => options.cycle = Some(parse_number(arg, value()?).map_err(Error::Usage)?),
+        "--render" => options.render = true,
+        "--image" => options.image = Some(PathBuf::from(value()?)),
+        "--scale" => match parse_number(arg, value()?).map_err(Error::Usage)? {
+          0 => return Err(Error::Usage("--scale must be positive".to_string())),
+          scale => options.scale = scale,
+        },
+        "--cost" => options.costs.update(value()?).map_err(Error::Usage)?,
+        "--horizontal" => options.boundary.horizontal = value()?.parse().map_err(Error::Usage)?,
+        "--vertical" => options.boundary.vertical = value()?.parse().map_err(Error::Usage)?,
+        "--steps" => {
+          options.boundary.steps = Some(parse_number(arg, value()?).map_err(Error::Usage)?)
+        }
+        _ => return Err(Error::Usage(format!("unknown argument {}", arg))),
+      }
+    }
+    if let Some(search) = &options.search {
+      if search.min_right > search.max_right || search.max_down == 0 {
+        return Err(Error::Usage(format!(
+          "empty search bounds, right {}..={} down 1..={}",
+          search.min_right, search.max_right, search.max_down
+        )));
+      }
+    }
+    // Each of these decides what is printed, --render and --image draw the
+    // same paths
+    let modes: Vec<&str> = [
+      ("--cycle", options.cycle.is_some()),
+      ("--route", options.route.is_some()),
+      ("--render", options.render),
+      ("--image", options.image.is_some() && !options.render),
+      ("--search", options.search.is_some()),
+    ]
+    .iter()
+    .filter(|&&(_, given)| given)
+    .map(|&(mode, _)| mode)
+    .collect();
+    if modes.len() > 1 {
+      return Err(Error::Usage(format!(
+        "{} cannot be combined",
+        modes.join(" and ")
+      )));
+    }
+    if !options.slopes.is_empty() && (options.route.is_some() || options.search.is_some()) {
+      return Err(Error::Usage(format!(
+        "--slope does not apply to {}",
+        modes[0]
+      )));
+    }
+    options.boundary.check().map_err(Error::Usage)?;
+    if options.boundary != Boundary::default() {
+      if options.cycle.is_some() {
+        return Err(Error::Usage(
+          "--cycle always repeats the map both ways, drop --horizontal, --vertical and --steps"
+            .to_string(),
+        ));
+      }
+      if options.route.is_some() {
+        if options.boundary.vertical != Edge::Stop || options.boundary.steps.is_some() {
+          return Err(Error::Usage(
+            "--route ends on the bottom row, only --horizontal applies".to_string(),
+          ));
+        }
+      } else if options.slopes.is_empty()
+        && options.search.is_none()
+        && !options.render
+        && options.image.is_none()
+      {
+        return Err(Error::Usage(
+          "the puzzle answers use its own boundary, add --slope, --search, --render, --image or --route"
+            .to_string(),
+        ));
+      }
+    }
+    if options.allow_left {
+      options.route().allow_left();
+    }
+    Ok(options)
+  }
+
+  fn search(&mut self) -> &mut Search {
+    self.search.get_or_insert_with(Search::default)
+  }
+
+  fn route(&mut self) -> &mut Routing {
+    self.route.get_or_insert_with(Routing::default)
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  #[error(transparent)]
+  Png(#[from] png::EncodingError),
+  #[error("line {line}: {found} squares wide, expected {expected}")]
+  Ragged {
+    line: usize,
+    expected: usize,
+    found: usize,
+  },
+  #[error("line {line}, column {column}: unknown terrain [{found}]")]
+  Terrain {
+    line: usize,
+    column: usize,
+    found: char,
+  },
+  #[error("{0}")]
+  Usage(String),
+}
+
+fn split_to_vec(row: &str, line: usize) -> Result<Vec<Terrain>, Error> {
+  row
+    .chars()
+    .enumerate()
+    .map(|(column, c)| {
+      Terrain::from_symbol(c).ok_or(Error::Terrain {
+        line,
+        column: column + 1,
+        found: c,
+      })
+    })
+    .collect()
+}
+
+fn read_from_file<P>(filename: P) -> Result<Vec<Vec<Terrain>>, Error>
 where
   P: AsRef<Path>,
 {
-  let mut tree_vec: Vec<Vec<char>> = Vec::new();
-  if let Ok(lines) = read_lines(filename) {
-    for line in lines {
-      if let Ok(row) = line {
-        tree_vec.push(split_to_vec(row))
+  parse_map(read_lines(filename)?)
+}
+
+// Every row must be as wide as the first
+fn parse_map<I>(rows: I) -> Result<Vec<Vec<Terrain>>, Error>
+where
+  I: IntoIterator<Item = io::Result<String>>,
+{
+  let mut tree_vec: Vec<Vec<Terrain>> = Vec::new();
+  for (i, row) in rows.into_iter().enumerate() {
+    let row = row?;
+    if row.trim().is_empty() {
+      continue;
+    }
+    let row = split_to_vec(row.trim_end(), i + 1)?;
+    if let Some(first) = tree_vec.first() {
+      if row.len() != first.len() {
+        return Err(Error::Ragged {
+          line: i + 1,
+          expected: first.len(),
+          found: row.len(),
+        });
       }
     }
+    tree_vec.push(row);
   }
-  tree_vec
+  Ok(tree_vec)
+}
+
+// The summed cost of every square the slope lands on
+fn cost_in_vec(
+  tree_vec: &[Vec<Terrain>],
+  slope_r: isize,
+  slope_d: usize,
+  costs: &CostTable,
+) -> u64 {
+  let slope = Slope {
+    right: slope_r,
+    down: slope_d,
+  };
+  cost_with_boundary(tree_vec, slope, Boundary::default(), costs)
 }
 
-fn count_trees_in_vec(tree_vec: &Vec<Vec<char>>, slope_r: usize, slope_d: usize) -> i32 {
-  let mut x = 0;
-  let mut count = 0;
-  for y in (0..tree_vec.len()).step_by(slope_d) {
-    if x >= tree_vec[y].len() {
-      x = x - tree_vec[y].len();
+fn cost_with_boundary(
+  tree_vec: &[Vec<Terrain>],
+  slope: Slope,
+  boundary: Boundary,
+  costs: &CostTable,
+) -> u64 {
+  Traversal::new(tree_vec, slope, boundary)
+    .map(|(x, y)| costs.cost(tree_vec[y][x]))
+    .sum()
+}
+
+fn count_trees_in_vec(tree_vec: &[Vec<Terrain>], slope_r: isize, slope_d: usize) -> u64 {
+  cost_in_vec(tree_vec, slope_r, slope_d, &CostTable::default())
+}
+
+// The tree map with every row packed into a bitset, one bit per column
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct TreeMap {
+  width: usize,
+  height: usize,
+  // u64 words per row
+  stride: usize,
+  bits: Vec<u64>,
+}
+
+impl TreeMap {
+  fn from_vec(tree_vec: &[Vec<Terrain>]) -> TreeMap {
+    let width = tree_vec.first().map_or(0, |row| row.len());
+    let stride = width.div_ceil(64);
+    let mut bits = vec![0u64; stride * tree_vec.len()];
+    for (y, row) in tree_vec.iter().enumerate() {
+      for (x, _) in row
+        .iter()
+        .take(width)
+        .enumerate()
+        .filter(|(_, &t)| t == Terrain::Tree)
+      {
+        bits[y * stride + x / 64] |= 1 << (x % 64);
+      }
     }
-    match tree_vec[y][x] {
-      '#' => count += 1,
-      _ => (),
+    TreeMap {
+      width,
+      height: tree_vec.len(),
+      stride,
+      bits,
     }
-    x += slope_r;
   }
 
-  count
+  fn is_tree(&self, x: usize, y: usize) -> bool {
+    self.bits[y * self.stride + x / 64] & (1 << (x % 64)) != 0
+  }
+
+  // Walks the rows once, advancing every slope that lands on the current row
+  fn count_trees(&self, slopes: &[Slope]) -> Vec<u64> {
+    let mut counts = vec![0u64; slopes.len()];
+    if self.width == 0 {
+      return counts;
+    }
+    let width = self.width as isize;
+    let steps: Vec<usize> = slopes
+      .iter()
+      .map(|slope| slope.right.rem_euclid(width) as usize)
+      .collect();
+    let mut xs = vec![0usize; slopes.len()];
+    for y in 0..self.height {
+      for (i, slope) in slopes.iter().enumerate() {
+        if y % slope.down != 0 {
+          continue;
+        }
+        if self.is_tree(xs[i], y) {
+          counts[i] += 1;
+        }
+        xs[i] = (xs[i] + steps[i]) % self.width;
+      }
+    }
+    counts
+  }
 }
 
-fn count_trees_all_slopes_in_vec(tree_vec: &Vec<Vec<char>>) -> Vec<i32> {
-  let mut tree_counts: Vec<i32> = Vec::new();
-  let slopes = vec![(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)];
+fn count_trees_slopes_in_vec(tree_vec: &[Vec<Terrain>], slopes: &[Slope]) -> Vec<u64> {
+  TreeMap::from_vec(tree_vec).count_trees(slopes)
+}
 
-  for (slope_r, slope_d) in slopes {
-    tree_counts.push(count_trees_in_vec(&tree_vec, slope_r, slope_d));
+// Plain tree counts within the puzzle's boundary go through the bitset
+// map, anything else walks every slope over the terrain
+fn cost_slopes_in_vec(
+  tree_vec: &[Vec<Terrain>],
+  slopes: &[Slope],
+  costs: &CostTable,
+  boundary: &Boundary,
+) -> Vec<u64> {
+  if costs.counts_trees() && *boundary == Boundary::default() {
+    return count_trees_slopes_in_vec(tree_vec, slopes);
   }
-  tree_counts
+  slopes
+    .iter()
+    .map(|&slope| cost_with_boundary(tree_vec, slope, *boundary, costs))
+    .collect()
 }
 
-pub fn count_trees() -> i32 {
-  let tree_vec = read_from_file("inputs/day3.txt");
-  count_trees_in_vec(&tree_vec, 3, 1)
+fn count_trees_all_slopes_in_vec(tree_vec: &[Vec<Terrain>]) -> Vec<u64> {
+  count_trees_slopes_in_vec(tree_vec, &PUZZLE_SLOPES)
 }
 
-pub fn count_trees_all_slopes() -> i64 {
-  let tree_vec = read_from_file("inputs/day3.txt");
-  count_trees_all_slopes_in_vec(&tree_vec)
+// None if the product does not fit in a u128
+fn product(counts: &[u64]) -> Option<u128> {
+  counts
     .iter()
-    .map(|&v| v as i64)
-    .product::<i64>()
+    .try_fold(1u128, |acc, &v| acc.checked_mul(v as u128))
+}
+
+// Every slope with its cost, best first according to the goal. Slopes
+// with equal costs keep the order they were given in.
+fn rank_slopes(
+  tree_vec: &[Vec<Terrain>],
+  slopes: &[Slope],
+  costs: &CostTable,
+  boundary: &Boundary,
+  goal: Goal,
+) -> Vec<(Slope, u64)> {
+  let counts = cost_slopes_in_vec(tree_vec, slopes, costs, boundary);
+  let mut ranking: Vec<(Slope, u64)> = slopes.iter().copied().zip(counts).collect();
+  match goal {
+    Goal::Fewest => ranking.sort_by_key(|&(_, count)| count),
+    Goal::Most => ranking.sort_by_key(|&(_, count)| std::cmp::Reverse(count)),
+  }
+  ranking
+}
+
+// The leading slopes of a ranking sharing the best count
+fn best_slopes(ranking: &[(Slope, u64)]) -> &[(Slope, u64)] {
+  let best = match ranking.first() {
+    Some(&(_, count)) => count,
+    None => return ranking,
+  };
+  let ties = ranking
+    .iter()
+    .take_while(|(_, count)| *count == best)
+    .count();
+  &ranking[..ties]
+}
+
+fn count_trees(tree_vec: &[Vec<Terrain>]) -> u64 {
+  count_trees_in_vec(tree_vec, 3, 1)
+}
+
+fn count_trees_all_slopes(tree_vec: &[Vec<Terrain>]) -> Option<u128> {
+  product(&count_trees_all_slopes_in_vec(tree_vec))
+}
+
+fn print_given_slopes(tree_vec: &[Vec<Terrain>], options: &Options) {
+  let (slopes, costs) = (&options.slopes, &options.costs);
+  let counts = cost_slopes_in_vec(tree_vec, slopes, costs, &options.boundary);
+  for (slope, count) in slopes.iter().zip(counts.iter()) {
+    if costs.counts_trees() {
+      println!("Slope {}: {} trees were encountered", slope, count);
+    } else {
+      println!("Slope {}: cost {}", slope, count);
+    }
+  }
+  let what = if costs.counts_trees() {
+    "trees encountered"
+  } else {
+    "costs"
+  };
+  match product(&counts) {
+    Some(product) => println!("{} product of all {}", product, what),
+    None => println!("Product of all {} overflows", what),
+  }
+}
+
+fn print_search(tree_vec: &[Vec<Terrain>], search: &Search, options: &Options) {
+  let costs = &options.costs;
+  let ranking = rank_slopes(
+    tree_vec,
+    &search.slopes(),
+    costs,
+    &options.boundary,
+    search.goal,
+  );
+  let best = best_slopes(&ranking);
+  let slopes: Vec<String> = best.iter().map(|(slope, _)| slope.to_string()).collect();
+  println!(
+    "{} {}: {} for {} {}",
+    match search.goal {
+      Goal::Fewest => "Fewest",
+      Goal::Most => "Most",
+    },
+    if costs.counts_trees() {
+      "trees"
+    } else {
+      "cost"
+    },
+    best.first().map_or(0, |&(_, count)| count),
+    if slopes.len() == 1 {
+      "slope"
+    } else {
+      "tied slopes"
+    },
+    slopes.join(" ")
+  );
+  if search.ranking {
+    for (rank, (slope, count)) in ranking.iter().enumerate() {
+      println!("{:>5}. {:>8} {}", rank + 1, slope.to_string(), count);
+    }
+  }
+}
+
+// The squares visited by a slope as (x, y). When the map repeats sideways
+// x is not wrapped, so the path can be drawn across the repeated map.
+fn path_in_vec(
+  tree_vec: &[Vec<Terrain>],
+  slope: &Slope,
+  boundary: &Boundary,
+) -> Vec<(isize, usize)> {
+  Traversal::new(tree_vec, *slope, *boundary)
+    .enumerate()
+    .map(|(step, (x, y))| match boundary.horizontal {
+      Edge::Wrap => (step as isize * slope.right, y),
+      _ => (x as isize, y),
+    })
+    .collect()
+}
+
+// The map repeated horizontally far enough to hold every path, and the
+// column of the original map's left edge within it
+struct Tiled {
+  width: usize,
+  height: usize,
+  origin: isize,
+  columns: usize,
+}
+
+impl Tiled {
+  fn new(tree_vec: &[Vec<Terrain>], paths: &[Vec<(isize, usize)>]) -> Tiled {
+    let width = tree_vec.first().map_or(1, |row| row.len().max(1));
+    let xs = paths.iter().flatten().map(|&(x, _)| x);
+    let min_x = xs.clone().min().unwrap_or(0).min(0);
+    let max_x = xs.max().unwrap_or(0).max(0);
+    let first_tile = min_x.div_euclid(width as isize);
+    let last_tile = max_x.div_euclid(width as isize);
+    Tiled {
+      width,
+      height: tree_vec.len(),
+      origin: -first_tile * width as isize,
+      columns: (last_tile - first_tile + 1) as usize * width,
+    }
+  }
+
+  fn square(&self, tree_vec: &[Vec<Terrain>], column: usize, y: usize) -> Terrain {
+    let x = (column as isize - self.origin).rem_euclid(self.width as isize) as usize;
+    tree_vec[y].get(x).copied().unwrap_or(Terrain::Open)
+  }
+}
+
+fn slope_paths(
+  tree_vec: &[Vec<Terrain>],
+  slopes: &[Slope],
+  boundary: &Boundary,
+) -> Vec<Vec<(isize, usize)>> {
+  slopes
+    .iter()
+    .map(|slope| path_in_vec(tree_vec, slope, boundary))
+    .collect()
+}
+
+// Draws the repeated map with `O` on visited open squares and `X` on
+// visited trees, as in the puzzle statement
+fn render_text(tree_vec: &[Vec<Terrain>], paths: &[Vec<(isize, usize)>]) -> String {
+  let tiled = Tiled::new(tree_vec, paths);
+  let mut rows: Vec<Vec<char>> = (0..tiled.height)
+    .map(|y| {
+      (0..tiled.columns)
+        .map(|column| tiled.square(tree_vec, column, y).symbol())
+        .collect()
+    })
+    .collect();
+  for &(x, y) in paths.iter().flatten() {
+    let column = (x + tiled.origin) as usize;
+    rows[y][column] = match tiled.square(tree_vec, column, y) {
+      Terrain::Tree => 'X',
+      _ => 'O',
+    };
+  }
+  rows
+    .iter()
+    .map(|row| row.iter().collect::<String>() + "\n")
+    .collect()
+}
+
+const OPEN_COLOR: [u8; 3] = [0xf4, 0xf4, 0xf0];
+const TREE_COLOR: [u8; 3] = [0x2e, 0x6b, 0x30];
+const ROCK_COLOR: [u8; 3] = [0x7f, 0x7f, 0x7f];
+const SNOW_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
+const ICE_COLOR: [u8; 3] = [0xb8, 0xdc, 0xf0];
+const SLOPE_COLORS: [[u8; 3]; 6] = [
+  [0xd6, 0x27, 0x28],
+  [0x1f, 0x77, 0xb4],
+  [0xff, 0x7f, 0x0e],
+  [0x94, 0x67, 0xbd],
+  [0x17, 0xbe, 0xcf],
+  [0xe3, 0x77, 0xc2],
+];
+
+// RGB pixels of the repeated map, `scale` pixels per square, with every
+// path in its own colour. Returns (width, height, pixels).
+fn render_image(
+  tree_vec: &[Vec<Terrain>],
+  paths: &[Vec<(isize, usize)>],
+  scale: usize,
+) -> (usize, usize, Vec<u8>) {
+  let tiled = Tiled::new(tree_vec, paths);
+  let mut squares: Vec<[u8; 3]> = (0..tiled.height)
+    .flat_map(|y| {
+      let tiled = &tiled;
+      (0..tiled.columns).map(move |column| match tiled.square(tree_vec, column, y) {
+        Terrain::Open => OPEN_COLOR,
+        Terrain::Tree => TREE_COLOR,
+        Terrain::Rock => ROCK_COLOR,
+        Terrain::Snow => SNOW_COLOR,
+        Terrain::Ice => ICE_COLOR,
+      })
+    })
+    .collect();
+  for (i, path) in paths.iter().enumerate() {
+    for &(x, y) in path {
+      squares[y * tiled.columns + (x + tiled.origin) as usize] =
+        SLOPE_COLORS[i % SLOPE_COLORS.len()];
+    }
+  }
+
+  let (width, height) = (tiled.columns * scale, tiled.height * scale);
+  let mut pixels = Vec::with_capacity(width * height * 3);
+  for y in 0..height {
+    for x in 0..width {
+      pixels.extend_from_slice(&squares[(y / scale) * tiled.columns + x / scale]);
+    }
+  }
+  (width, height, pixels)
+}
+
+// Writes a PNG if the path ends in .png, otherwise a binary PPM
+fn write_image(path: &Path, width: usize, height: usize, pixels: &[u8]) -> Result<(), Error> {
+  let mut out = BufWriter::new(File::create(path)?);
+  let is_png = path
+    .extension()
+    .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+  if is_png {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+  } else {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    out.write_all(pixels)?;
+    out.flush()?;
+  }
+  Ok(())
+}
+
+fn print_render(
+  tree_vec: &[Vec<Terrain>],
+  paths: &[Vec<(isize, usize)>],
+  options: &Options,
+) -> Result<(), Error> {
+  if options.render {
+    print!("{}", render_text(tree_vec, paths));
+  }
+  if let Some(path) = &options.image {
+    let (width, height, pixels) = render_image(tree_vec, paths, options.scale);
+    write_image(path, width, height, &pixels)?;
+    println!("Wrote {}x{} image to {}", width, height, path.display());
+  }
+  Ok(())
+}
+
+// The cheapest way down found by `find_route`, with x not wrapped when the
+// map repeats sideways
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Route {
+  cost: u64,
+  path: Vec<(isize, usize)>,
+}
+
+// Finds the cheapest path from `start` on the top row, or from any square on
+// it when there is no start, to any square on the bottom row, taking one of
+// `moves` per step. Every move goes down at least
+// one row, so the squares can be settled row by row. `edge` decides where
+// moves that leave the map sideways end up.
+fn find_route(
+  tree_vec: &[Vec<Terrain>],
+  start: Option<usize>,
+  moves: &[Slope],
+  edge: Edge,
+  costs: &CostTable,
+) -> Option<Route> {
+  let height = tree_vec.len();
+  let width = tree_vec.first().map_or(0, |row| row.len());
+  if width == 0 || start.is_some_and(|start| start >= width) {
+    return None;
+  }
+  let cost_at =
+    |x: usize, y: usize| costs.cost(tree_vec[y].get(x).copied().unwrap_or(Terrain::Open));
+  // Cheapest cost to every square, and the square and move it was reached by
+  let mut best: Vec<Option<u64>> = vec![None; width * height];
+  let mut reached_by: Vec<Option<(usize, usize)>> = vec![None; width * height];
+  for x in start.map_or(0..width, |start| start..start + 1) {
+    best[x] = Some(cost_at(x, 0));
+  }
+
+  for y in 0..height {
+    for x in 0..width {
+      let cost = match best[y * width + x] {
+        Some(cost) => cost,
+        None => continue,
+      };
+      for (m, step) in moves.iter().enumerate() {
+        let ny = y + step.down;
+        if ny >= height {
+          continue;
+        }
+        let nx = match edge.resolve(x as i128 + step.right as i128, width) {
+          Some(nx) => nx,
+          None => continue,
+        };
+        let next = cost + cost_at(nx, ny);
+        let square = ny * width + nx;
+        if best[square].is_none_or(|b| next < b) {
+          best[square] = Some(next);
+          reached_by[square] = Some((y * width + x, m));
+        }
+      }
+    }
+  }
+
+  let last = (height - 1) * width;
+  let (mut square, cost) = (last..last + width)
+    .filter_map(|square| best[square].map(|cost| (square, cost)))
+    .min_by_key(|&(_, cost)| cost)?;
+  // Every square after the start with the move that reached it, which leaves
+  // the start on the top row
+  let mut steps = Vec::new();
+  while let Some((previous, m)) = reached_by[square] {
+    steps.push((square, moves[m]));
+    square = previous;
+  }
+  let mut path = vec![(square as isize, 0)];
+  for &(square, step) in steps.iter().rev() {
+    let &(x, _) = path.last().unwrap();
+    let x = match edge {
+      Edge::Wrap => x + step.right,
+      _ => (square % width) as isize,
+    };
+    path.push((x, square / width));
+  }
+  Some(Route { cost, path })
+}
+
+fn print_route(
+  tree_vec: &[Vec<Terrain>],
+  options: &Options,
+  routing: &Routing,
+) -> Result<(), Error> {
+  let route = find_route(
+    tree_vec,
+    routing.start,
+    &routing.moves,
+    options.boundary.horizontal,
+    &options.costs,
+  );
+  match route {
+    Some(route) => {
+      println!(
+        "Least {}: {} over {} steps",
+        if options.costs.counts_trees() {
+          "trees"
+        } else {
+          "cost"
+        },
+        route.cost,
+        route.path.len() - 1
+      );
+      let squares: Vec<String> = route
+        .path
+        .iter()
+        .map(|(x, y)| format!("{},{}", x, y))
+        .collect();
+      println!("Path: {}", squares.join(" "));
+      print_render(tree_vec, &[route.path], options)?;
+    }
+    None => println!("No route reaches the bottom row"),
+  }
+  Ok(())
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+  if b == 0 {
+    a
+  } else {
+    gcd(b, a % b)
+  }
+}
+
+// On a map repeated in both directions every slope returns to the start
+// square. `prefix[k]` holds the cost of the first k squares of one cycle.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Cycle {
+  length: u64,
+  per_cycle: u64,
+  prefix: Vec<u64>,
+}
+
+impl Cycle {
+  // x repeats after width / gcd(width, right) steps and y after
+  // height / gcd(height, down), so the squares repeat after the lcm of both
+  fn new(tree_vec: &[Vec<Terrain>], slope: Slope, costs: &CostTable) -> Option<Cycle> {
+    let width = tree_vec.first().map_or(0, |row| row.len()) as u64;
+    let height = tree_vec.len() as u64;
+    if width == 0 {
+      return None;
+    }
+    let right = slope.right.rem_euclid(width as isize) as u64;
+    let down = slope.down as u64 % height;
+    let period_x = width / gcd(width, right);
+    let period_y = height / gcd(height, down);
+    let length = period_x / gcd(period_x, period_y) * period_y;
+
+    let torus = Boundary {
+      horizontal: Edge::Wrap,
+      vertical: Edge::Wrap,
+      steps: Some(length),
+    };
+    let mut prefix = Vec::with_capacity(length as usize + 1);
+    prefix.push(0);
+    for (x, y) in Traversal::new(tree_vec, slope, torus) {
+      prefix.push(prefix.last().unwrap() + costs.cost(tree_vec[y][x]));
+    }
+    Some(Cycle {
+      length,
+      per_cycle: prefix[length as usize],
+      prefix,
+    })
+  }
+
+  // The cost of the first `steps` squares, starting square included
+  fn cost_after(&self, steps: u64) -> u128 {
+    (steps / self.length) as u128 * self.per_cycle as u128
+      + self.prefix[(steps % self.length) as usize] as u128
+  }
+}
+
+fn print_cycles(tree_vec: &[Vec<Terrain>], steps: u64, options: &Options) {
+  let slopes = if options.slopes.is_empty() {
+    vec![Slope { right: 3, down: 1 }]
+  } else {
+    options.slopes.clone()
+  };
+  let what = if options.costs.counts_trees() {
+    "trees"
+  } else {
+    "cost"
+  };
+  for slope in slopes {
+    match Cycle::new(tree_vec, slope, &options.costs) {
+      Some(cycle) => println!(
+        "Slope {}: repeats every {} steps with {} {}, {} {} after {} steps",
+        slope,
+        cycle.length,
+        cycle.per_cycle,
+        what,
+        cycle.cost_after(steps),
+        what,
+        steps
+      ),
+      None => println!("Slope {}: the map is empty", slope),
+    }
+  }
+}
+
+pub fn day_3(options: &Options) -> Result<(), Error> {
+  let tree_vec = read_from_file(&options.input)?;
+  if let Some(steps) = options.cycle {
+    print_cycles(&tree_vec, steps, options);
+  } else if let Some(routing) = &options.route {
+    print_route(&tree_vec, options, routing)?;
+  } else if options.render || options.image.is_some() {
+    let slopes = if options.slopes.is_empty() {
+      vec![Slope { right: 3, down: 1 }]
+    } else {
+      options.slopes.clone()
+    };
+    let paths = slope_paths(&tree_vec, &slopes, &options.boundary);
+    print_render(&tree_vec, &paths, options)?;
+  } else if let Some(search) = &options.search {
+    print_search(&tree_vec, search, options);
+  } else if !options.slopes.is_empty() {
+    print_given_slopes(&tree_vec, options);
+  } else {
+    println!(
+      "Puzzle 1: {} trees were encountered",
+      count_trees(&tree_vec)
+    );
+    println!(
+      "Puzzle 2: {} product of all trees encounterd",
+      count_trees_all_slopes(&tree_vec).unwrap_or_default()
+    );
+  }
+  Ok(())
 }
 
 #[test]
 fn count_trees_as_expected() {
-  let tree_vec = read_from_file("inputs/day3_test.txt");
+  let tree_vec = read_from_file("inputs/day3_test.txt").unwrap();
   assert_eq!(count_trees_in_vec(&tree_vec, 3, 1), 7)
 }
 
 #[test]
 fn count_trees_all_slopes_as_expectes() {
-  let tree_vec = read_from_file("inputs/day3_test.txt");
+  let tree_vec = read_from_file("inputs/day3_test.txt").unwrap();
   assert_eq!(
     count_trees_all_slopes_in_vec(&tree_vec),
     vec![2, 7, 3, 4, 2]
   )
 }
+
+#[test]
+fn count_trees_leftward_as_expected() {
+  let tree_vec = read_from_file("inputs/day3_test.txt").unwrap();
+  // Going left on the mirrored map is the same as going right on the original
+  let mirrored: Vec<Vec<Terrain>> = tree_vec
+    .iter()
+    .map(|row| {
+      let mut row = row.clone();
+      row.reverse();
+      row.rotate_right(1);
+      row
+    })
+    .collect();
+  assert_eq!(count_trees_in_vec(&mirrored, -3, 1), 7);
+  assert_eq!(count_trees_in_vec(&tree_vec, 14, 1), 7);
+}
+
+#[test]
+fn parse_slope_expected() {
+  assert_eq!(Slope::from_str("-2,3"), Ok(Slope { right: -2, down: 3 }));
+  assert!(Slope::from_str("1,0").is_err());
+  assert!(Slope::from_str("1").is_err());
+  assert!(Slope::from_str("1,2,3").is_err());
+}
+
+#[test]
+fn tree_map_matches_count_trees_in_vec() {
+  // A pseudo random 300 x 1000 map
+  let mut seed: u64 = 0x2020_1203;
+  let tree_vec: Vec<Vec<Terrain>> = (0..1000)
+    .map(|_| {
+      (0..300)
+        .map(|_| {
+          seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+          if seed >> 61 == 0 {
+            Terrain::Tree
+          } else {
+            Terrain::Open
+          }
+        })
+        .collect()
+    })
+    .collect();
+  let slopes: Vec<Slope> = (-40..=40)
+    .flat_map(|right| (1..=5).map(move |down| Slope { right, down }))
+    .collect();
+  let expected: Vec<u64> = slopes
+    .iter()
+    .map(|slope| count_trees_in_vec(&tree_vec, slope.right, slope.down))
+    .collect();
+  assert_eq!(count_trees_slopes_in_vec(&tree_vec, &slopes), expected);
+}
+
+#[test]
+fn search_slopes_as_expected() {
+  let tree_vec = read_from_file("inputs/day3_test.txt").unwrap();
+  let search = Search {
+    min_right: 1,
+    max_right: 7,
+    max_down: 2,
+    ..Search::default()
+  };
+  let ranking = rank_slopes(
+    &tree_vec,
+    &search.slopes(),
+    &CostTable::default(),
+    &Boundary::default(),
+    Goal::Most,
+  );
+  assert_eq!(best_slopes(&ranking), &[(Slope { right: 3, down: 1 }, 7)]);
+  assert_eq!(ranking.len(), 14);
+
+  let ranking = rank_slopes(
+    &tree_vec,
+    &PUZZLE_SLOPES,
+    &CostTable::default(),
+    &Boundary::default(),
+    Goal::Fewest,
+  );
+  assert_eq!(
+    best_slopes(&ranking),
+    &[
+      (Slope { right: 1, down: 1 }, 2),
+      (Slope { right: 1, down: 2 }, 2)
+    ]
+  );
+}
+
+// The puzzle's example map, before it was repeated in the test input
+#[cfg(test)]
+fn example_map() -> Vec<Vec<Terrain>> {
+  read_from_file("inputs/day3_test.txt")
+    .unwrap()
+    .iter()
+    .map(|row| row[..11].to_vec())
+    .collect()
+}
+
+#[test]
+fn render_text_as_expected() {
+  let tree_vec = example_map();
+  let rendered = render_text(
+    &tree_vec,
+    &slope_paths(
+      &tree_vec,
+      &[Slope { right: 3, down: 1 }],
+      &Boundary::default(),
+    ),
+  );
+  let rows: Vec<&str> = rendered.lines().collect();
+  assert_eq!(rows.len(), 11);
+  assert_eq!(rows[0], "O.##.........##.........##.......");
+  assert_eq!(rows[1], "#..O#...#..#...#...#..#...#...#..");
+  assert_eq!(rows[2], ".#....X..#..#....#..#..#....#..#.");
+  assert_eq!(rows[10], ".#..#...#.#.#..#...#.#.#..#...X.#");
+  assert_eq!(rendered.matches('X').count(), 7);
+
+  let rendered = render_text(
+    &tree_vec,
+    &slope_paths(
+      &tree_vec,
+      &[Slope { right: -1, down: 2 }],
+      &Boundary::default(),
+    ),
+  );
+  let rows: Vec<&str> = rendered.lines().collect();
+  assert_eq!(rows[0], "..##.......O.##.......");
+  assert_eq!(rows[2], ".#....#..#O.#....#..#.");
+}
+
+#[test]
+fn render_image_as_expected() {
+  let tree_vec = example_map();
+  let slopes = [Slope { right: 1, down: 1 }, Slope { right: 3, down: 1 }];
+  let (width, height, pixels) = render_image(
+    &tree_vec,
+    &slope_paths(&tree_vec, &slopes, &Boundary::default()),
+    2,
+  );
+  assert_eq!((width, height), (66, 22));
+  assert_eq!(pixels.len(), 66 * 22 * 3);
+  // Square (1, 1) belongs to the first slope, (3, 1) to the second
+  assert_eq!(pixels[(2 * 66 + 2) * 3..][..3], SLOPE_COLORS[0]);
+  assert_eq!(pixels[(2 * 66 + 6) * 3..][..3], SLOPE_COLORS[1]);
+  assert_eq!(pixels[(2 * 66 + 4) * 3..][..3], OPEN_COLOR);
+}
+
+#[test]
+fn terrain_map_rejects_unknown_squares() {
+  assert_eq!(
+    split_to_vec(".#^*~", 1).unwrap(),
+    vec![
+      Terrain::Open,
+      Terrain::Tree,
+      Terrain::Rock,
+      Terrain::Snow,
+      Terrain::Ice
+    ]
+  );
+  match split_to_vec("..#x.", 4) {
+    Err(Error::Terrain {
+      line: 4,
+      column: 4,
+      found: 'x',
+    }) => (),
+    other => panic!("unexpected {:?}", other),
+  }
+}
+
+#[test]
+fn cost_in_vec_as_expected() {
+  let tree_vec = map_from_rows(&["..#", ".^.", "..*", "..#"]).unwrap();
+  let mut costs = CostTable::default();
+  costs.update("tree=2,rock=5,snow=1").unwrap();
+  assert!(!costs.counts_trees());
+  // Lands on . ^ * . going one right
+  assert_eq!(cost_in_vec(&tree_vec, 1, 1, &costs), 6);
+  // Lands on . . going right, . # going left, every third row
+  assert_eq!(cost_in_vec(&tree_vec, 1, 3, &costs), 0);
+  assert_eq!(cost_in_vec(&tree_vec, -1, 3, &costs), 2);
+  assert_eq!(
+    cost_slopes_in_vec(
+      &tree_vec,
+      &[Slope { right: -1, down: 3 }],
+      &costs,
+      &Boundary::default()
+    ),
+    vec![2]
+  );
+  assert!(costs.update("lava=3").is_err());
+  assert!(costs.update("tree").is_err());
+}
+
+#[test]
+fn find_route_as_expected() {
+  let tree_vec = example_map();
+  let costs = CostTable::default();
+  let moves = Routing::default().moves;
+  let route = find_route(&tree_vec, Some(0), &moves, Edge::Wrap, &costs).unwrap();
+  assert_eq!(route.cost, 0);
+  assert_eq!(route.path.len(), 11);
+  for &(x, y) in route.path.iter() {
+    assert_eq!(tree_vec[y][x.rem_euclid(11) as usize], Terrain::Open);
+  }
+
+  // With a single move the route is the slope itself
+  let slope = Slope { right: 1, down: 1 };
+  let route = find_route(&tree_vec, Some(0), &[slope], Edge::Wrap, &costs).unwrap();
+  assert_eq!(route.cost, count_trees_in_vec(&tree_vec, 1, 1));
+  assert_eq!(
+    route.path,
+    path_in_vec(&tree_vec, &slope, &Boundary::default())
+  );
+  let left = [Slope { right: -1, down: 1 }];
+  assert!(find_route(&tree_vec, Some(0), &left, Edge::Stop, &costs).is_none());
+
+  // Clamped moves stay on the left edge, so the path matches a clamped slope
+  let route = find_route(&tree_vec, Some(0), &left, Edge::Clamp, &costs).unwrap();
+  let clamp = Boundary {
+    horizontal: Edge::Clamp,
+    ..Boundary::default()
+  };
+  assert_eq!(route.path, path_in_vec(&tree_vec, &left[0], &clamp));
+  assert_eq!(route.path[3], (0, 3));
+
+  // Without a start the cheapest column on the top row is picked
+  let tree_vec = map_from_rows(&["#..", "#..", "#.."]).unwrap();
+  let down = [Slope { right: 0, down: 1 }];
+  let route = find_route(&tree_vec, Some(0), &down, Edge::Stop, &costs).unwrap();
+  assert_eq!(route.cost, 3);
+  let route = find_route(&tree_vec, None, &down, Edge::Stop, &costs).unwrap();
+  assert_eq!(route.cost, 0);
+  assert_eq!(route.path, vec![(1, 0), (1, 1), (1, 2)]);
+  assert!(find_route(&tree_vec, Some(3), &down, Edge::Stop, &costs).is_none());
+}
+
+#[test]
+fn routing_moves_from_args() {
+  let args: Vec<String> = ["--move", "1,1", "--move", "2,2", "--allow-left"]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+  let options = Options::from_args(&args).unwrap();
+  let moves: Vec<String> = options
+    .route
+    .unwrap()
+    .moves
+    .iter()
+    .map(|step| step.to_string())
+    .collect();
+  assert_eq!(moves, vec!["1,1", "2,2", "-1,1", "-2,2"]);
+
+  let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+  let options = Options::from_args(&args(&["--route", "--horizontal", "clamp"])).unwrap();
+  assert_eq!(options.boundary.horizontal, Edge::Clamp);
+  assert!(Options::from_args(&args(&["--render", "--image", "out.png", "--slope", "1,1"])).is_ok());
+  let options = Options::from_args(&args(&["--no-wrap"])).unwrap();
+  assert_eq!(options.boundary.horizontal, Edge::Stop);
+  for rejected in &[
+    &["--route", "--vertical", "wrap", "--steps", "5"][..],
+    &["--cycle", "10", "--horizontal", "stop"],
+    &["--horizontal", "clamp"],
+    &["--search", "--render"],
+    &["--route", "--cycle", "10"],
+    &["--image", "out.png", "--search"],
+    &["--slope", "1,1", "--route"],
+  ] {
+    assert!(Options::from_args(&args(rejected)).is_err());
+  }
+}
+
+#[cfg(test)]
+fn map_from_rows(rows: &[&str]) -> Result<Vec<Vec<Terrain>>, Error> {
+  parse_map(rows.iter().map(|row| Ok(row.to_string())))
+}
+
+#[test]
+fn parse_map_rejects_ragged_rows() {
+  match map_from_rows(&["..#", ".#", "..."]) {
+    Err(Error::Ragged {
+      line: 2,
+      expected: 3,
+      found: 2,
+    }) => (),
+    other => panic!("unexpected {:?}", other),
+  }
+}
+
+#[test]
+fn boundary_modes_as_expected() {
+  let tree_vec = map_from_rows(&["#..", ".#.", "..#", "#.."]).unwrap();
+  let costs = CostTable::default();
+  let slope = Slope { right: 4, down: 1 };
+  let walk = |boundary: Boundary| -> Vec<(usize, usize)> {
+    Traversal::new(&tree_vec, slope, boundary).collect()
+  };
+  // Modular wrapping for a step wider than the map
+  let wrap = Boundary::default();
+  assert_eq!(walk(wrap), vec![(0, 0), (1, 1), (2, 2), (0, 3)]);
+  assert_eq!(cost_with_boundary(&tree_vec, slope, wrap, &costs), 4);
+  assert_eq!(count_trees_in_vec(&tree_vec, 4, 1), 4);
+
+  let clamp = Boundary {
+    horizontal: Edge::Clamp,
+    ..Boundary::default()
+  };
+  assert_eq!(walk(clamp), vec![(0, 0), (2, 1), (2, 2), (2, 3)]);
+
+  let stop = Boundary {
+    horizontal: Edge::Stop,
+    ..Boundary::default()
+  };
+  assert_eq!(walk(stop), vec![(0, 0)]);
+
+  let torus = Boundary {
+    vertical: Edge::Wrap,
+    steps: Some(6),
+    ..Boundary::default()
+  };
+  assert_eq!(
+    walk(torus),
+    vec![(0, 0), (1, 1), (2, 2), (0, 3), (1, 0), (2, 1)]
+  );
+  assert_eq!(cost_with_boundary(&tree_vec, slope, torus, &costs), 4);
+
+  let floor = Boundary {
+    vertical: Edge::Clamp,
+    steps: Some(6),
+    ..Boundary::default()
+  };
+  assert_eq!(walk(floor)[4..], [(1, 3), (2, 3)]);
+  assert!(Boundary {
+    vertical: Edge::Wrap,
+    ..Boundary::default()
+  }
+  .check()
+  .is_err());
+}
+
+#[test]
+fn cycle_matches_traversal() {
+  let tree_vec = example_map();
+  let costs = CostTable::default();
+  for &(right, down) in &[(3, 1), (1, 2), (-2, 3), (11, 1), (5, 11), (22, 22)] {
+    let slope = Slope { right, down };
+    let cycle = Cycle::new(&tree_vec, slope, &costs).unwrap();
+    let torus = |steps| Boundary {
+      horizontal: Edge::Wrap,
+      vertical: Edge::Wrap,
+      steps: Some(steps),
+    };
+    let walk: Vec<(usize, usize)> =
+      Traversal::new(&tree_vec, slope, torus(cycle.length + 1)).collect();
+    assert_eq!(walk[cycle.length as usize], (0, 0));
+    assert!(!walk[1..cycle.length as usize].contains(&(0, 0)));
+    for &steps in &[0, 1, 7, cycle.length, cycle.length * 3 + 5, 500] {
+      assert_eq!(
+        cycle.cost_after(steps),
+        cost_with_boundary(&tree_vec, slope, torus(steps), &costs) as u128
+      );
+    }
+  }
+  let cycle = Cycle::new(&tree_vec, Slope { right: 3, down: 1 }, &costs).unwrap();
+  assert_eq!(cycle.length, 11);
+  assert_eq!(cycle.cost_after(11), 7);
+  assert_eq!(cycle.cost_after(1_000_000_000_000), 636_363_636_363);
+}
@@ -1,124 +1,2194 @@
-use super::utils::parse_newline_sep;
+use super::utils::parse_newline_sep_numbered;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(parse_display::FromStr, Debug)]
+// The puzzle's rules, used unless a schema is given with --schema
+const DEFAULT_SCHEMA: &str = r##"
+[[field]]
+name = "byr"
+required = true
+type = "integer"
+range = [1920, 2002]
+
+[[field]]
+name = "iyr"
+required = true
+type = "integer"
+range = [2010, 2020]
+
+[[field]]
+name = "eyr"
+required = true
+type = "integer"
+range = [2020, 2030]
+
+[[field]]
+name = "hgt"
+required = true
+type = "height"
+units = { cm = [150, 193], in = [59, 76] }
+
+[[field]]
+name = "hcl"
+required = true
+type = "color"
+
+[[field]]
+name = "ecl"
+required = true
+type = "eye-color"
+
+[[field]]
+name = "pid"
+required = true
+type = "passport-id"
+length = 9
+
+[[field]]
+name = "cid"
+"##;
+
+lazy_static! {
+  static ref PUZZLE_SCHEMA: Schema = Schema::from_str(DEFAULT_SCHEMA).unwrap();
+}
+
+// Every key the puzzle knows about
+const KNOWN_KEYS: [&str; 8] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid", "cid"];
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum ParseMode {
+  // Repeated keys overwrite and unknown keys are kept as they are. A token
+  // that is not a key:value pair or a year that does not parse rejects the
+  // record, which is reported and skipped.
+  #[default]
+  Lenient,
+  // Every problem is reported as a diagnostic and the record is kept
+  Strict,
+  // Lenient, but tokens that are not key:value pairs are kept for the fixers
+  // to join back. Only used by --normalize.
+  Repair,
+}
+
+impl FromStr for ParseMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim() {
+      "lenient" => Ok(ParseMode::Lenient),
+      "strict" => Ok(ParseMode::Strict),
+      _ => Err(format!(
+        "unknown parse mode {}, expected lenient or strict",
+        s
+      )),
+    }
+  }
+}
+
+// A problem found while reading a passport in strict mode
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Diagnostic {
+  // A field without exactly one colon
+  NotAPair { field: String },
+  Duplicate { key: String },
+  UnknownKey { key: String },
+  EmptyValue { key: String },
+  TypeError { key: String, value: String },
+}
+
+impl Diagnostic {
+  // The kind of problem and the key it was found on, used to group them
+  fn rule(&self) -> String {
+    match self {
+      Diagnostic::NotAPair { .. } => "not a key:value pair".to_string(),
+      Diagnostic::Duplicate { key } => format!("{} duplicate", key),
+      Diagnostic::UnknownKey { key } => format!("{} unknown key", key),
+      Diagnostic::EmptyValue { key } => format!("{} empty", key),
+      Diagnostic::TypeError { key, .. } => format!("{} wrong type", key),
+    }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Diagnostic::NotAPair { field } => write!(f, "[{}] is not a key:value pair", field),
+      Diagnostic::Duplicate { key } => write!(f, "duplicate {}", key),
+      Diagnostic::UnknownKey { key } => write!(f, "unknown key {}", key),
+      Diagnostic::EmptyValue { key } => write!(f, "{} is empty", key),
+      Diagnostic::TypeError { key, value } => write!(f, "{} [{}] has the wrong type", key, value),
+    }
+  }
+}
+
+// How passports are stored in a file
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+  // The puzzle's blank line separated key:value records
+  #[default]
+  Batch,
+  Csv,
+  // One JSON object per line
+  JsonLines,
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim() {
+      "batch" => Ok(Format::Batch),
+      "csv" => Ok(Format::Csv),
+      "jsonl" => Ok(Format::JsonLines),
+      _ => Err(format!(
+        "unknown format {}, expected batch, csv or jsonl",
+        s
+      )),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+struct Year(u32);
+
+impl FromStr for Year {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    s.parse()
+      .map(Year)
+      .map_err(|e| format!("invalid year {}: {}", s, e))
+  }
+}
+
+impl fmt::Display for Year {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Clone, Copy, PartialEq)]
 enum Height {
+  // Feet and inches together, like 5ft11in
+  #[display("{0}ft{1}in")]
+  FtIn(u32, u32),
   #[display("{0}cm")]
   Cm(u32),
+  #[display("{0}mm")]
+  Mm(u32),
   #[display("{0}in")]
   In(u32),
 }
 
-#[derive(Debug, Default)]
-struct Passport {
-  byr: Option<u32>,
-  iyr: Option<u32>,
-  eyr: Option<u32>,
-  hgt_set: bool,
-  hgt: Option<Height>,
-  hcl: Option<String>,
-  ecl: Option<String>,
-  pid: Option<String>,
+impl Height {
+  // Whole tenths of a millimetre, exact for every unit
+  fn tenths_of_mm(&self) -> u64 {
+    match *self {
+      Height::FtIn(ft, inches) => (ft as u64 * 12 + inches as u64) * 254,
+      Height::Cm(cm) => cm as u64 * 100,
+      Height::Mm(mm) => mm as u64 * 10,
+      Height::In(inches) => inches as u64 * 254,
+    }
+  }
+
+  fn centimetres(&self) -> f64 {
+    self.tenths_of_mm() as f64 / 100.0
+  }
+
+  fn inches(&self) -> f64 {
+    self.tenths_of_mm() as f64 / 254.0
+  }
+
+  // The unit the height was written in, ft-in is not a unit of its own
+  fn unit(&self) -> &'static str {
+    match self {
+      Height::FtIn(..) => "ft-in",
+      Height::Cm(_) => "cm",
+      Height::Mm(_) => "mm",
+      Height::In(_) => "in",
+    }
+  }
+
+  // Tenths of a millimetre in one of a unit a schema range can use
+  fn unit_size(unit: &str) -> Option<u64> {
+    match unit {
+      "cm" => Some(100),
+      "mm" => Some(10),
+      "in" => Some(254),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct HairColor {
+  r: u8,
+  g: u8,
+  b: u8,
+}
+
+impl FromStr for HairColor {
+  type Err = String;
+
+  // #rrggbb in either case
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || format!("invalid colour {}", s);
+    let hex = s.strip_prefix('#').ok_or_else(err)?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+      return Err(err());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| err());
+    Ok(HairColor {
+      r: channel(0)?,
+      g: channel(2)?,
+      b: channel(4)?,
+    })
+  }
+}
+
+impl fmt::Display for HairColor {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+  }
+}
+
+#[derive(
+  parse_display::Display, parse_display::FromStr, Debug, Clone, Copy, Eq, PartialEq, Hash,
+)]
+#[display(style = "lowercase")]
+enum EyeColor {
+  Amb,
+  Blu,
+  Brn,
+  Gry,
+  Grn,
+  Hzl,
+  Oth,
+}
+
+// Kept as text so leading zeros survive
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct PassportId(String);
+
+impl FromStr for PassportId {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+      return Err(format!("invalid passport id {}", s));
+    }
+    Ok(PassportId(s.to_string()))
+  }
+}
+
+impl fmt::Display for PassportId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Passport {
+  byr: Option<Year>,
+  iyr: Option<Year>,
+  eyr: Option<Year>,
+  hgt: Option<Height>,
+  hcl: Option<HairColor>,
+  ecl: Option<EyeColor>,
+  pid: Option<PassportId>,
+  // Every key and value as read, in order, including cid and unknown keys
+  fields: Vec<(String, String)>,
+  // Tokens that are not key:value pairs, with their position among all the
+  // tokens read. Lenient mode rejects the record instead.
+  stray: Vec<(usize, String)>,
+  // How many tokens were on each line, so the record can be written back
+  layout: Vec<usize>,
+  // Only filled in strict mode
+  diagnostics: Vec<Diagnostic>,
+}
+
+impl Passport {
+  // Every field puzzle 1 asks for is present, whether or not it is well typed
+  fn is_valid(&self) -> bool {
+    KNOWN_KEYS
+      .iter()
+      .filter(|&&key| key != "cid")
+      .all(|key| self.value(key).is_some())
+  }
+
+  fn tokens(&self) -> usize {
+    self.fields.len() + self.stray.len()
+  }
+
+  // Indices into `stray` of the pid parts right after the pid, like the 345
+  // and 678 of pid:012 345 678
+  fn pid_parts(&self) -> std::ops::Range<usize> {
+    let field = match self.fields.iter().rposition(|(key, _)| key == "pid") {
+      Some(field) => field,
+      None => return 0..0,
+    };
+    // Stray tokens before the pid move it along
+    let (mut at, mut first) = (field, 0);
+    while first < self.stray.len() && self.stray[first].0 <= at {
+      at += 1;
+      first += 1;
+    }
+    let mut end = first;
+    while end < self.stray.len()
+      && self.stray[end].0 == at + 1 + end - first
+      && is_pid_part(&self.stray[end].1)
+    {
+      end += 1;
+    }
+    first..end
+  }
+
+  // Drops a stray token, keeping the layout of the rest
+  fn remove_stray(&mut self, i: usize) {
+    let (at, _) = self.stray.remove(i);
+    for (later, _) in &mut self.stray[i..] {
+      *later -= 1;
+    }
+    let mut end = 0;
+    for count in &mut self.layout {
+      end += *count;
+      if at < end {
+        *count -= 1;
+        break;
+      }
+    }
+  }
+
+  // The raw value of a field, the last one wins if the key is repeated
+  fn value(&self, key: &str) -> Option<&str> {
+    self
+      .fields
+      .iter()
+      .rev()
+      .find(|(k, _)| k == key)
+      .map(|(_, v)| v.as_str())
+  }
+
+  // Replaces the value that value() returns
+  fn set_value(&mut self, key: &str, value: String) {
+    if let Some((_, v)) = self.fields.iter_mut().rev().find(|(k, _)| k == key) {
+      *v = value;
+    }
+  }
+
+  fn parse(s: &str, mode: ParseMode) -> Result<Passport, String> {
+    let mut passport = Passport::default();
+
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+      let count = passport.tokens();
+      for field in line.split_whitespace() {
+        passport.parse_field(field, mode)?;
+      }
+      passport.layout.push(passport.tokens() - count);
+    }
+    Ok(passport)
+  }
+
+  // Reads a key:value pair into the typed view and the raw fields
+  fn parse_field(&mut self, field: &str, mode: ParseMode) -> Result<(), String> {
+    let strict = mode == ParseMode::Strict;
+    let mut pair = field.split(":");
+    let (key, value) = match (pair.next(), pair.next(), pair.next()) {
+      (Some(key), Some(value), None) => (key, value),
+      (_, None, _) if mode == ParseMode::Lenient => Err(format!("field incorrect {}", field))?,
+      _ if mode == ParseMode::Lenient => Err(format!("field invalid {}", field))?,
+      _ => {
+        if strict {
+          self.diagnostics.push(Diagnostic::NotAPair {
+            field: field.to_string(),
+          });
+        }
+        self.stray.push((self.tokens(), field.to_string()));
+        return Ok(());
+      }
+    };
+
+    if strict {
+      if self.value(key).is_some() {
+        self.diagnostics.push(Diagnostic::Duplicate {
+          key: key.to_string(),
+        });
+      }
+      if !KNOWN_KEYS.contains(&key) {
+        self.diagnostics.push(Diagnostic::UnknownKey {
+          key: key.to_string(),
+        });
+      }
+      if value.is_empty() {
+        self.diagnostics.push(Diagnostic::EmptyValue {
+          key: key.to_string(),
+        });
+      }
+    }
+    // Years must parse even in lenient mode, the rest are left unset
+    let year = |value: &str| match value.parse::<Year>() {
+      Ok(year) => Ok(Some(year)),
+      Err(_) if strict => Ok(None),
+      Err(e) => Err(e),
+    };
+
+    let typed = match key {
+      "byr" => set(&mut self.byr, year(value)?),
+      "iyr" => set(&mut self.iyr, year(value)?),
+      "eyr" => set(&mut self.eyr, year(value)?),
+      "hgt" => set(&mut self.hgt, value.parse().ok()),
+      "hcl" => set(&mut self.hcl, value.parse().ok()),
+      "ecl" => set(&mut self.ecl, value.parse().ok()),
+      "pid" => set(&mut self.pid, value.parse().ok()),
+      // cid and unknown keys are only kept in the raw fields
+      _ => true,
+    };
+    if strict && !typed && !value.is_empty() {
+      self.diagnostics.push(Diagnostic::TypeError {
+        key: key.to_string(),
+        value: value.to_string(),
+      });
+    }
+    self.fields.push((key.to_string(), value.to_string()));
+    Ok(())
+  }
+}
+
+// Stores a typed value, true if there was one
+fn set<T>(slot: &mut Option<T>, value: Option<T>) -> bool {
+  *slot = value;
+  slot.is_some()
+}
+
+impl FromStr for Passport {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Passport::parse(s, ParseMode::Lenient)
+  }
+}
+
+// Reads a passport in strict mode
+struct StrictPassport(Passport);
+
+impl FromStr for StrictPassport {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Passport::parse(s, ParseMode::Strict).map(StrictPassport)
+  }
+}
+
+// Reads a passport keeping the tokens that are not key:value pairs
+struct RepairPassport(Passport);
+
+impl FromStr for RepairPassport {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Passport::parse(s, ParseMode::Repair).map(RepairPassport)
+  }
+}
+
+// Writes the fields back in the batch format, one line per line read
+impl fmt::Display for Passport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut fields = self
+      .fields
+      .iter()
+      .map(|(key, value)| format!("{}:{}", key, value));
+    let mut stray = self.stray.iter().peekable();
+    let mut tokens = (0..).map_while(|at| match stray.next_if(|(i, _)| *i == at) {
+      Some((_, token)) => Some(token.clone()),
+      None => fields.next(),
+    });
+    let mut lines: Vec<Vec<String>> = self
+      .layout
+      .iter()
+      .map(|&count| tokens.by_ref().take(count).collect())
+      .collect();
+    let rest: Vec<String> = tokens.collect();
+    if !rest.is_empty() {
+      lines.push(rest);
+    }
+    let lines: Vec<String> = lines.iter().map(|line| line.join(" ")).collect();
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+// Writes passports in the batch format, separated by blank lines
+fn write_batch<'a, W: Write>(
+  mut writer: W,
+  passports: impl IntoIterator<Item = &'a Passport>,
+) -> io::Result<()> {
+  for (i, passport) in passports.into_iter().enumerate() {
+    if i > 0 {
+      writeln!(writer)?;
+    }
+    writeln!(writer, "{}", passport)?;
+  }
+  writer.flush()
+}
+
+// A passport as a CSV row, with a column for every known key
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CsvRecord {
+  line: usize,
+  byr: Option<String>,
+  iyr: Option<String>,
+  eyr: Option<String>,
+  hgt: Option<String>,
+  hcl: Option<String>,
+  ecl: Option<String>,
+  pid: Option<String>,
+  cid: Option<String>,
+  // Unknown fields as space separated key:value pairs, then stray tokens
+  other: Option<String>,
+  // hgt converted to cm and in, only written on export
+  #[serde(default, skip_deserializing)]
+  height_cm: Option<f64>,
+  #[serde(default, skip_deserializing)]
+  height_in: Option<f64>,
+  // Has every field puzzle 1 asks for
+  complete: bool,
+  valid: bool,
+  // Separated by "; "
+  reasons: Option<String>,
+}
+
+// A passport as a line of JSON, keeping the fields and their layout
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JsonRecord {
+  line: usize,
+  fields: Vec<(String, String)>,
+  #[serde(default)]
+  stray: Vec<(usize, String)>,
+  #[serde(default)]
+  layout: Vec<usize>,
+  complete: bool,
+  valid: bool,
+  reasons: Vec<String>,
+}
+
+impl CsvRecord {
+  fn new(line: usize, passport: &Passport, schema: &Schema) -> Self {
+    let value = |key| passport.value(key).map(str::to_string);
+    let other: Vec<String> = passport
+      .fields
+      .iter()
+      .filter(|(key, _)| !KNOWN_KEYS.contains(&key.as_str()))
+      .map(|(key, value)| format!("{}:{}", key, value))
+      .chain(passport.stray.iter().map(|(_, token)| token.clone()))
+      .collect();
+    let reasons = schema.reasons(passport);
+    CsvRecord {
+      line,
+      byr: value("byr"),
+      iyr: value("iyr"),
+      eyr: value("eyr"),
+      hgt: value("hgt"),
+      hcl: value("hcl"),
+      ecl: value("ecl"),
+      pid: value("pid"),
+      cid: value("cid"),
+      other: Some(other.join(" ")).filter(|other| !other.is_empty()),
+      height_cm: passport.hgt.map(|hgt| hgt.centimetres()),
+      height_in: passport.hgt.map(|hgt| hgt.inches()),
+      complete: passport.is_valid(),
+      valid: reasons.is_empty(),
+      reasons: Some(reasons.join("; ")).filter(|reasons| !reasons.is_empty()),
+    }
+  }
+
+  // The record in the batch format, all on one line
+  fn to_batch(&self) -> String {
+    let known = [
+      ("byr", &self.byr),
+      ("iyr", &self.iyr),
+      ("eyr", &self.eyr),
+      ("hgt", &self.hgt),
+      ("hcl", &self.hcl),
+      ("ecl", &self.ecl),
+      ("pid", &self.pid),
+      ("cid", &self.cid),
+    ];
+    let mut fields: Vec<String> = known
+      .iter()
+      .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}:{}", key, value)))
+      .collect();
+    fields.extend(self.other.iter().cloned());
+    fields.join(" ")
+  }
+}
+
+impl JsonRecord {
+  fn new(line: usize, passport: &Passport, schema: &Schema) -> Self {
+    let reasons = schema.reasons(passport);
+    JsonRecord {
+      line,
+      fields: passport.fields.clone(),
+      stray: passport.stray.clone(),
+      layout: passport.layout.clone(),
+      complete: passport.is_valid(),
+      valid: reasons.is_empty(),
+      reasons,
+    }
+  }
+
+  fn to_batch(&self) -> String {
+    Passport {
+      fields: self.fields.clone(),
+      stray: self.stray.clone(),
+      layout: self.layout.clone(),
+      ..Passport::default()
+    }
+    .to_string()
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldType {
+  #[default]
+  String,
+  Integer,
+  // A whole number followed by a unit, like 183cm
+  Measure,
+  // A Height, compared with the unit ranges after converting between units
+  Height,
+  // A HairColor written in lowercase
+  Color,
+  #[serde(rename = "eye-color")]
+  EyeColor,
+  #[serde(rename = "passport-id")]
+  PassportId,
+}
+
+// A field rule as written in a schema file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldSpec {
+  name: String,
+  #[serde(default)]
+  required: bool,
+  #[serde(default, rename = "type")]
+  kind: FieldType,
+  range: Option<(i64, i64)>,
+  regex: Option<String>,
+  one_of: Option<Vec<String>>,
+  // The allowed range for every unit of a measure or height
+  units: Option<BTreeMap<String, (i64, i64)>>,
+  // The exact number of characters
+  length: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaFile {
+  #[serde(default, rename = "field")]
+  fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Clone)]
+struct FieldRule {
+  spec: FieldSpec,
+  regex: Option<Regex>,
+}
+
+impl FieldRule {
+  // Every way the value breaks this rule. Typed fields are checked through
+  // the passport's typed view of them.
+  fn check(&self, value: &str, passport: &Passport) -> Vec<Violation> {
+    let spec = &self.spec;
+    let field = || spec.name.clone();
+    let mut violations = Vec::new();
+    let out_of_range = |n: i64, unit: Option<&str>, (min, max): (i64, i64)| {
+      if (min..=max).contains(&n) {
+        None
+      } else {
+        Some(Violation::OutOfRange {
+          field: field(),
+          value: n,
+          unit: unit.map(str::to_string),
+          min,
+          max,
+        })
+      }
+    };
+    match spec.kind {
+      FieldType::String => (),
+      FieldType::Integer => match value.parse::<i64>() {
+        Ok(n) => {
+          if let Some(range) = spec.range {
+            violations.extend(out_of_range(n, None, range));
+          }
+        }
+        Err(_) => violations.push(Violation::NotInteger {
+          field: field(),
+          value: value.to_string(),
+        }),
+      },
+      FieldType::Measure => match split_measure(value) {
+        Some((n, unit)) => {
+          match spec.units.as_ref().map(|units| units.get(unit)) {
+            Some(Some(&range)) => violations.extend(out_of_range(n, Some(unit), range)),
+            Some(None) => violations.push(Violation::UnknownUnit {
+              field: field(),
+              value: value.to_string(),
+            }),
+            None => (),
+          }
+          if let Some(range) = spec.range {
+            violations.extend(out_of_range(n, Some(unit), range));
+          }
+        }
+        None => violations.push(Violation::NotMeasure {
+          field: field(),
+          value: value.to_string(),
+        }),
+      },
+      FieldType::Height => match value.parse::<Height>() {
+        Ok(height) => violations.extend(self.check_height(height)),
+        Err(_) if split_measure(value).is_some() => violations.push(Violation::UnknownUnit {
+          field: field(),
+          value: value.to_string(),
+        }),
+        Err(_) => violations.push(Violation::NotMeasure {
+          field: field(),
+          value: value.to_string(),
+        }),
+      },
+      FieldType::Color => {
+        let color = match spec.name.as_str() {
+          "hcl" => passport.hcl,
+          _ => value.parse::<HairColor>().ok(),
+        };
+        if !is_canonical(color.as_ref(), value) {
+          violations.push(Violation::Malformed {
+            field: field(),
+            value: value.to_string(),
+          })
+        }
+      }
+      FieldType::EyeColor => {
+        let color = match spec.name.as_str() {
+          "ecl" => passport.ecl,
+          _ => value.parse::<EyeColor>().ok(),
+        };
+        if !is_canonical(color.as_ref(), value) {
+          violations.push(Violation::NotAllowed {
+            field: field(),
+            value: value.to_string(),
+          })
+        }
+      }
+      FieldType::PassportId => {
+        let pid = match spec.name.as_str() {
+          "pid" => passport.pid.clone(),
+          _ => value.parse::<PassportId>().ok(),
+        };
+        if !is_canonical(pid.as_ref(), value) {
+          violations.push(Violation::Malformed {
+            field: field(),
+            value: value.to_string(),
+          })
+        }
+      }
+    }
+    if let Some(length) = spec.length {
+      let found = value.chars().count();
+      if found != length {
+        violations.push(Violation::Length {
+          field: field(),
+          value: value.to_string(),
+          found,
+          expected: length,
+        });
+      }
+    }
+    if let Some(re) = &self.regex {
+      if !re.is_match(value) {
+        violations.push(Violation::Malformed {
+          field: field(),
+          value: value.to_string(),
+        });
+      }
+    }
+    if let Some(set) = &spec.one_of {
+      if !set.iter().any(|v| v == value) {
+        violations.push(Violation::NotAllowed {
+          field: field(),
+          value: value.to_string(),
+        });
+      }
+    }
+    violations
+  }
+
+  // A height fits if it is within the range of any unit once converted to
+  // it. Out of range heights are reported in their own unit when the schema
+  // has a range for it.
+  fn check_height(&self, height: Height) -> Option<Violation> {
+    let units = self.spec.units.as_ref().filter(|units| !units.is_empty())?;
+    let tenths = height.tenths_of_mm() as i64;
+    let size = |unit: &str| Height::unit_size(unit).unwrap_or(1) as i64;
+    let fits = units
+      .iter()
+      .any(|(unit, &(min, max))| (min * size(unit)..=max * size(unit)).contains(&tenths));
+    if fits {
+      return None;
+    }
+    let (unit, &(min, max)) = units
+      .iter()
+      .find(|(unit, _)| unit.as_str() == height.unit())
+      .or_else(|| units.iter().next())?;
+    Some(Violation::OutOfRange {
+      field: self.spec.name.clone(),
+      value: (tenths as f64 / size(unit) as f64).round() as i64,
+      unit: Some(unit.clone()),
+      min,
+      max,
+    })
+  }
+}
+
+// Typed values must parse and be written the way they display
+fn is_canonical<T: fmt::Display>(typed: Option<&T>, value: &str) -> bool {
+  typed.is_some_and(|t| t.to_string() == value)
+}
+
+// A way a passport breaks its schema
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Violation {
+  Missing {
+    field: String,
+  },
+  NotInteger {
+    field: String,
+    value: String,
+  },
+  NotMeasure {
+    field: String,
+    value: String,
+  },
+  UnknownUnit {
+    field: String,
+    value: String,
+  },
+  OutOfRange {
+    field: String,
+    value: i64,
+    unit: Option<String>,
+    min: i64,
+    max: i64,
+  },
+  Length {
+    field: String,
+    value: String,
+    found: usize,
+    expected: usize,
+  },
+  // Does not match the field's regex or type
+  Malformed {
+    field: String,
+    value: String,
+  },
+  // Not one of the field's allowed values
+  NotAllowed {
+    field: String,
+    value: String,
+  },
+}
+
+impl Violation {
+  fn field(&self) -> &str {
+    match self {
+      Violation::Missing { field }
+      | Violation::NotInteger { field, .. }
+      | Violation::NotMeasure { field, .. }
+      | Violation::UnknownUnit { field, .. }
+      | Violation::OutOfRange { field, .. }
+      | Violation::Length { field, .. }
+      | Violation::Malformed { field, .. }
+      | Violation::NotAllowed { field, .. } => field,
+    }
+  }
+
+  // The broken rule, used to group violations
+  fn rule(&self) -> String {
+    let rule = match self {
+      Violation::Missing { .. } => "missing".to_string(),
+      Violation::NotInteger { .. } => "not an integer".to_string(),
+      Violation::NotMeasure { .. } => "not a measure".to_string(),
+      Violation::UnknownUnit { .. } => "unknown unit".to_string(),
+      Violation::OutOfRange {
+        unit: Some(unit), ..
+      } => format!("out of range for {}", unit),
+      Violation::OutOfRange { unit: None, .. } => "out of range".to_string(),
+      Violation::Length { .. } => "wrong length".to_string(),
+      Violation::Malformed { .. } => "malformed".to_string(),
+      Violation::NotAllowed { .. } => "not allowed".to_string(),
+    };
+    format!("{} {}", self.field(), rule)
+  }
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Violation::Missing { field } => write!(f, "missing {}", field),
+      Violation::NotInteger { field, value } => {
+        write!(f, "{} [{}] is not an integer", field, value)
+      }
+      Violation::NotMeasure { field, value } => {
+        write!(f, "{} [{}] is not a number with a unit", field, value)
+      }
+      Violation::UnknownUnit { field, value } => {
+        write!(f, "{} [{}] has an unknown unit", field, value)
+      }
+      Violation::OutOfRange {
+        field,
+        value,
+        unit,
+        min,
+        max,
+      } => {
+        let unit = unit.as_deref().unwrap_or_default();
+        write!(
+          f,
+          "{} {}{} out of range {}{}..={}{}",
+          field, value, unit, min, unit, max, unit
+        )
+      }
+      Violation::Length {
+        field,
+        value,
+        found,
+        expected,
+      } => write!(
+        f,
+        "{} [{}] has {} characters, expected {}",
+        field, value, found, expected
+      ),
+      Violation::Malformed { field, value } => write!(f, "{} [{}] is malformed", field, value),
+      Violation::NotAllowed { field, value } => write!(f, "{} [{}] is not allowed", field, value),
+    }
+  }
+}
+
+// Splits "183cm" into (183, "cm")
+fn split_measure(value: &str) -> Option<(i64, &str)> {
+  let digits = value
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(value.len());
+  let number = value[..digits].parse::<i64>().ok()?;
+  Some((number, &value[digits..]))
+}
+
+// A repair for a common mistake in a passport field
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Fixer {
+  // Lowercases a hex colour like #ABC123
+  HexCase,
+  // Adds the unit to a bare height when only one unit in the schema fits
+  HeightUnit,
+  // Joins a pid split by spaces or dashes back into digits
+  PidDigits,
+}
+
+const FIXERS: [Fixer; 3] = [Fixer::HexCase, Fixer::HeightUnit, Fixer::PidDigits];
+
+impl FromStr for Fixer {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.trim() {
+      "hex-case" => Ok(Fixer::HexCase),
+      "height-unit" => Ok(Fixer::HeightUnit),
+      "pid-digits" => Ok(Fixer::PidDigits),
+      _ => Err(format!(
+        "unknown fixer {}, expected hex-case, height-unit or pid-digits",
+        s
+      )),
+    }
+  }
+}
+
+// A field a fixer rewrote
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Change {
+  field: String,
+  from: String,
+  to: String,
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} [{}] -> [{}]", self.field, self.from, self.to)
+  }
+}
+
+impl Fixer {
+  // The field and its new value, if the fixer applies
+  fn fix(self, passport: &Passport, schema: &Schema) -> Option<(&'static str, String)> {
+    match self {
+      Fixer::HexCase => Some(("hcl", passport.hcl?.to_string())),
+      Fixer::HeightUnit => {
+        let hgt = passport.value("hgt")?;
+        let n = hgt.parse::<i64>().ok()?;
+        let units = schema.rule("hgt")?.spec.units.as_ref()?;
+        let mut fits = units
+          .iter()
+          .filter(|(_, &(min, max))| (min..=max).contains(&n));
+        match (fits.next(), fits.next()) {
+          (Some((unit, _)), None) => Some(("hgt", format!("{}{}", n, unit))),
+          _ => None,
+        }
+      }
+      Fixer::PidDigits => {
+        // Digits split off by whitespace end up as stray tokens after the pid
+        let pid = passport.value("pid")?;
+        let parts = passport.stray[passport.pid_parts()]
+          .iter()
+          .map(|(_, token)| token.as_str());
+        let joined: String = std::iter::once(pid).chain(parts).collect();
+        let digits: String = joined.chars().filter(char::is_ascii_digit).collect();
+        if digits.is_empty() || !is_pid_part(&joined) {
+          return None;
+        }
+        Some(("pid", digits))
+      }
+    }
+  }
+}
+
+// Digits, maybe grouped with dashes, like 012-345
+fn is_pid_part(s: &str) -> bool {
+  !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+// Applies the fixers in order and parses the result again, so the typed view
+// and diagnostics match the new values
+fn normalize(
+  passport: &Passport,
+  fixers: &[Fixer],
+  schema: &Schema,
+  mode: ParseMode,
+) -> Result<(Passport, Vec<Change>), String> {
+  // The typed view is only brought up to date by parsing the result again
+  let mut fixed = passport.clone();
+  let mut changes = Vec::new();
+  for fixer in fixers {
+    if let Some((field, to)) = fixer.fix(&fixed, schema) {
+      let from = fixed.value(field).unwrap_or_default().to_string();
+      if from != to {
+        if *fixer == Fixer::PidDigits {
+          // The digits joined onto the pid are no longer stray tokens
+          for i in fixed.pid_parts().rev() {
+            fixed.remove_stray(i);
+          }
+        }
+        fixed.set_value(field, to.clone());
+        changes.push(Change {
+          field: field.to_string(),
+          from,
+          to,
+        });
+      }
+    }
+  }
+  Ok((Passport::parse(&fixed.to_string(), mode)?, changes))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(String),
+  // A quoted string, never a keyword
+  Text(String),
+  Op(CompareOp),
+  Open,
+  Close,
+  Comma,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum CompareOp {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+}
+
+impl CompareOp {
+  fn holds(self, ordering: std::cmp::Ordering) -> bool {
+    use std::cmp::Ordering::*;
+    match self {
+      CompareOp::Eq => ordering == Equal,
+      CompareOp::Ne => ordering != Equal,
+      CompareOp::Lt => ordering == Less,
+      CompareOp::Le => ordering != Greater,
+      CompareOp::Gt => ordering == Greater,
+      CompareOp::Ge => ordering != Less,
+    }
+  }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut chars = s.chars().peekable();
+  while let Some(&c) = chars.peek() {
+    match c {
+      c if c.is_whitespace() => {
+        chars.next();
+      }
+      '(' | ')' | ',' => {
+        chars.next();
+        tokens.push(match c {
+          '(' => Token::Open,
+          ')' => Token::Close,
+          _ => Token::Comma,
+        });
+      }
+      '=' | '!' | '<' | '>' => {
+        chars.next();
+        let eq = chars.next_if_eq(&'=').is_some();
+        tokens.push(Token::Op(match (c, eq) {
+          ('=', _) => CompareOp::Eq,
+          ('!', true) => CompareOp::Ne,
+          ('<', false) => CompareOp::Lt,
+          ('<', true) => CompareOp::Le,
+          ('>', false) => CompareOp::Gt,
+          ('>', true) => CompareOp::Ge,
+          _ => return Err("expected = after !".to_string()),
+        }));
+      }
+      '"' | '\'' => {
+        chars.next();
+        let mut text = String::new();
+        loop {
+          match chars.next() {
+            Some(q) if q == c => break,
+            Some(q) => text.push(q),
+            None => return Err(format!("unterminated {}{}", c, text)),
+          }
+        }
+        tokens.push(Token::Text(text));
+      }
+      _ => {
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "_.#-".contains(*c)) {
+          word.push(c);
+        }
+        if word.is_empty() {
+          return Err(format!("unexpected {}", c));
+        }
+        tokens.push(Token::Word(word));
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+// What a comparison looks at
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+  // The raw value of a field
+  Field(String),
+  // hgt converted to cm, in or mm, like hgt.cm
+  Height(String),
+}
+
+impl FromStr for Operand {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.split_once('.') {
+      None => Ok(Operand::Field(s.to_string())),
+      Some(("hgt", unit)) if ["cm", "in", "mm"].contains(&unit) => {
+        Ok(Operand::Height(unit.to_string()))
+      }
+      Some(_) => Err(format!("unknown field {}", s)),
+    }
+  }
+}
+
+impl Operand {
+  fn value(&self, passport: &Passport) -> Option<String> {
+    match self {
+      Operand::Field(key) => passport.value(key).map(str::to_string),
+      Operand::Height(unit) => {
+        let hgt = passport.hgt?;
+        let value = match unit.as_str() {
+          "cm" => hgt.centimetres(),
+          "in" => hgt.inches(),
+          _ => hgt.centimetres() * 10.0,
+        };
+        Some(value.to_string())
+      }
+    }
+  }
+}
+
+// A query over passports, like: ecl in (blu,grn) and hgt.cm >= 170 and not valid
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+  And(Box<Filter>, Box<Filter>),
+  Or(Box<Filter>, Box<Filter>),
+  Not(Box<Filter>),
+  // Passes the schema
+  Valid,
+  // Has every field puzzle 1 asks for
+  Complete,
+  // A bare field name, true if the field is there
+  Present(Operand),
+  Compare(Operand, CompareOp, String),
+  In(Operand, Vec<String>),
+}
+
+impl FromStr for Filter {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let tokens = tokenize(s)?;
+    let mut parser = FilterParser { tokens, next: 0 };
+    let filter = parser.or()?;
+    match parser.tokens.get(parser.next) {
+      None => Ok(filter),
+      Some(token) => Err(format!("unexpected {:?}", token)),
+    }
+  }
+}
+
+struct FilterParser {
+  tokens: Vec<Token>,
+  next: usize,
 }
 
-impl Passport {
-  fn is_valid(&self) -> bool {
-    self.byr.is_some()
-      && self.iyr.is_some()
-      && self.eyr.is_some()
-      && self.hgt_set
-      && self.hcl.is_some()
-      && self.ecl.is_some()
-      && self.pid.is_some()
+impl FilterParser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.next)
+  }
+
+  fn advance(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.next).cloned();
+    self.next += 1;
+    token
+  }
+
+  fn keyword(&mut self, keyword: &str) -> bool {
+    let found = matches!(self.peek(), Some(Token::Word(w)) if w == keyword);
+    if found {
+      self.next += 1;
+    }
+    found
+  }
+
+  fn expect(&mut self, expected: Token) -> Result<(), String> {
+    match self.advance() {
+      Some(token) if token == expected => Ok(()),
+      Some(token) => Err(format!("expected {:?}, found {:?}", expected, token)),
+      None => Err(format!("expected {:?} at the end", expected)),
+    }
+  }
+
+  fn or(&mut self) -> Result<Filter, String> {
+    let mut filter = self.and()?;
+    while self.keyword("or") {
+      filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+    }
+    Ok(filter)
+  }
+
+  fn and(&mut self) -> Result<Filter, String> {
+    let mut filter = self.unary()?;
+    while self.keyword("and") {
+      filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+    }
+    Ok(filter)
+  }
+
+  fn unary(&mut self) -> Result<Filter, String> {
+    if self.keyword("not") {
+      return Ok(Filter::Not(Box::new(self.unary()?)));
+    }
+    match self.advance() {
+      Some(Token::Open) => {
+        let filter = self.or()?;
+        self.expect(Token::Close)?;
+        Ok(filter)
+      }
+      Some(Token::Word(word)) => match word.as_str() {
+        "valid" => Ok(Filter::Valid),
+        "complete" => Ok(Filter::Complete),
+        "and" | "or" | "in" => Err(format!("unexpected {}", word)),
+        _ => self.comparison(word.parse()?),
+      },
+      Some(token) => Err(format!("unexpected {:?}", token)),
+      None => Err("unexpected end of filter".to_string()),
+    }
   }
 
-  fn is_valid_values_opt(&self) -> Option<bool> {
-    lazy_static! {
-      static ref HAIR_COLOR_RE: Regex = Regex::new(r"^#[0-9a-f]{6}$").unwrap();
-      static ref EYE_COLOR_RE: Regex = Regex::new(r"^(amb|blu|brn|gry|grn|hzl|oth)$").unwrap();
-      static ref PASSPORT_ID_RE: Regex = Regex::new(r"^\d{9}$").unwrap();
+  fn comparison(&mut self, operand: Operand) -> Result<Filter, String> {
+    if self.keyword("in") {
+      self.expect(Token::Open)?;
+      let mut values = vec![self.value()?];
+      while self.peek() == Some(&Token::Comma) {
+        self.next += 1;
+        values.push(self.value()?);
+      }
+      self.expect(Token::Close)?;
+      return Ok(Filter::In(operand, values));
     }
-    let valid = (1920..=2002).contains(&self.byr?)
-      && (2010..=2020).contains(&self.iyr?)
-      && (2020..=2030).contains(&self.eyr?)
-      && match self.hgt.as_ref()? {
-        Height::Cm(cm) => (150..=193).contains(cm),
-        Height::In(inch) => (59..=76).contains(inch),
+    match self.peek() {
+      Some(&Token::Op(op)) => {
+        self.next += 1;
+        Ok(Filter::Compare(operand, op, self.value()?))
       }
-      && HAIR_COLOR_RE.is_match(self.hcl.as_ref()?)
-      && EYE_COLOR_RE.is_match(self.ecl.as_ref()?)
-      && PASSPORT_ID_RE.is_match(self.pid.as_ref()?);
-    Some(valid)
+      _ => Ok(Filter::Present(operand)),
+    }
   }
 
-  fn is_valid_values(&self) -> bool {
-    self.is_valid_values_opt().unwrap_or_default()
+  fn value(&mut self) -> Result<String, String> {
+    match self.advance() {
+      Some(Token::Word(value)) | Some(Token::Text(value)) => Ok(value),
+      Some(token) => Err(format!("expected a value, found {:?}", token)),
+      None => Err("expected a value at the end".to_string()),
+    }
   }
 }
 
-impl FromStr for Passport {
+// Numbers compare as numbers, anything else as text
+fn compare_values(a: &str, b: &str) -> std::cmp::Ordering {
+  match (a.parse::<f64>(), b.parse::<f64>()) {
+    (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal),
+    _ => a.cmp(b),
+  }
+}
+
+impl Filter {
+  // A missing field fails every comparison
+  fn matches(&self, passport: &Passport, schema: &Schema) -> bool {
+    match self {
+      Filter::And(a, b) => a.matches(passport, schema) && b.matches(passport, schema),
+      Filter::Or(a, b) => a.matches(passport, schema) || b.matches(passport, schema),
+      Filter::Not(filter) => !filter.matches(passport, schema),
+      Filter::Valid => schema.is_valid(passport),
+      Filter::Complete => passport.is_valid(),
+      Filter::Present(operand) => operand.value(passport).is_some(),
+      Filter::Compare(operand, op, value) => operand
+        .value(passport)
+        .is_some_and(|v| op.holds(compare_values(&v, value))),
+      Filter::In(operand, values) => operand.value(passport).is_some_and(|v| {
+        values
+          .iter()
+          .any(|value| compare_values(&v, value) == std::cmp::Ordering::Equal)
+      }),
+    }
+  }
+}
+
+// Validation rules for passport fields, see DEFAULT_SCHEMA for the format
+#[derive(Debug, Clone)]
+pub struct Schema {
+  rules: Vec<FieldRule>,
+}
+
+impl FromStr for Schema {
   type Err = String;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let mut passport = Passport::default();
+    let file: SchemaFile = toml::from_str(s).map_err(|e| e.to_string())?;
+    let rules = file
+      .fields
+      .into_iter()
+      .map(|spec| {
+        let regex = match &spec.regex {
+          Some(re) => Some(
+            Regex::new(re)
+              .map_err(|e| format!("field {} has an invalid regex: {}", spec.name, e))?,
+          ),
+          None => None,
+        };
+        if spec.kind == FieldType::Height {
+          if spec.range.is_some() {
+            return Err(format!(
+              "field {} is a height, give its ranges in units",
+              spec.name
+            ));
+          }
+          let units = spec.units.iter().flat_map(|units| units.keys());
+          if let Some(unit) = units
+            .into_iter()
+            .find(|unit| Height::unit_size(unit).is_none())
+          {
+            return Err(format!(
+              "field {} has an unknown height unit {}, expected cm, mm or in",
+              spec.name, unit
+            ));
+          }
+        }
+        Ok(FieldRule { spec, regex })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+    Ok(Schema { rules })
+  }
+}
 
-    for field in s.split_whitespace() {
-      let mut pair = field.split(":");
-      let key = pair
-        .next()
-        .ok_or_else(|| format!("field incorrect {}", field))?;
-      let value = pair
-        .next()
-        .ok_or_else(|| format!("field incorrect {}", field))?;
-      if pair.next().is_some() {
-        Err(format!("field invalid {}", field))?;
-      }
+impl Schema {
+  fn load(path: &Path) -> Result<Schema, Error> {
+    let content = fs::read_to_string(path)?;
+    Schema::from_str(&content).map_err(|e| Error::Schema(path.to_path_buf(), e))
+  }
 
-      match key {
-        "byr" => passport.byr = Some(value.parse::<u32>().map_err(|e| e.to_string())?),
-        "iyr" => passport.iyr = Some(value.parse::<u32>().map_err(|e| e.to_string())?),
-        "eyr" => passport.eyr = Some(value.parse::<u32>().map_err(|e| e.to_string())?),
-        "hgt" => {
-          passport.hgt_set = true;
-          passport.hgt = value.parse().ok();
-        }
-        "hcl" => passport.hcl = Some(value.to_string()),
-        "ecl" => passport.ecl = Some(value.to_string()),
-        "pid" => passport.pid = Some(value.to_string()),
-        _ => {
-          // Skip the rest
+  // A passport with strict mode diagnostics is never valid
+  fn is_valid(&self, passport: &Passport) -> bool {
+    passport.diagnostics.is_empty() && self.validate(passport).is_empty()
+  }
+
+  // Strict mode diagnostics followed by schema violations, as text
+  fn reasons(&self, passport: &Passport) -> Vec<String> {
+    passport
+      .diagnostics
+      .iter()
+      .map(|d| d.to_string())
+      .chain(self.validate(passport).iter().map(|v| v.to_string()))
+      .collect()
+  }
+
+  // The rule behind every reason, used to group them
+  fn broken_rules(&self, passport: &Passport) -> Vec<String> {
+    passport
+      .diagnostics
+      .iter()
+      .map(|d| d.rule())
+      .chain(self.validate(passport).iter().map(|v| v.rule()))
+      .collect()
+  }
+
+  fn rule(&self, name: &str) -> Option<&FieldRule> {
+    self.rules.iter().find(|rule| rule.spec.name == name)
+  }
+
+  // Every violation of every rule, in schema order
+  fn validate(&self, passport: &Passport) -> Vec<Violation> {
+    self
+      .rules
+      .iter()
+      .flat_map(|rule| match passport.value(&rule.spec.name) {
+        Some(value) => rule.check(value, passport),
+        None if rule.spec.required => vec![Violation::Missing {
+          field: rule.spec.name.clone(),
+        }],
+        None => Vec::new(),
+      })
+      .collect()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  // Read one after the other, --input can be given more than once
+  inputs: Vec<PathBuf>,
+  schema: Option<PathBuf>,
+  report: bool,
+  mode: ParseMode,
+  input_format: Format,
+  // Where to write the passports back out, "-" for stdout
+  emit: Option<PathBuf>,
+  format: Format,
+  // Fix what can be fixed and emit the result
+  normalize: bool,
+  fixers: Vec<Fixer>,
+  stats: bool,
+  duplicates: bool,
+  filter: Option<Filter>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      inputs: vec![PathBuf::from("inputs/day4.txt")],
+      schema: None,
+      report: false,
+      mode: ParseMode::Lenient,
+      input_format: Format::Batch,
+      emit: None,
+      format: Format::Batch,
+      normalize: false,
+      fixers: FIXERS.to_vec(),
+      stats: false,
+      duplicates: false,
+      filter: None,
+    }
+  }
+}
+
+impl Options {
+  pub fn from_args(args: &[String]) -> Result<Self, Error> {
+    let mut options = Options::default();
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| Error::Usage(format!("{} expects a value", arg)))
+      };
+      match arg.as_str() {
+        "--input" => inputs.push(PathBuf::from(value()?)),
+        "--schema" => options.schema = Some(PathBuf::from(value()?)),
+        "--report" => options.report = true,
+        "--mode" => options.mode = value()?.parse().map_err(Error::Usage)?,
+        "--input-format" => options.input_format = value()?.parse().map_err(Error::Usage)?,
+        "--emit" => options.emit = Some(PathBuf::from(value()?)),
+        "--format" => options.format = value()?.parse().map_err(Error::Usage)?,
+        "--normalize" => options.normalize = true,
+        "--stats" => options.stats = true,
+        "--duplicates" => options.duplicates = true,
+        "--filter" => options.filter = Some(value()?.parse().map_err(Error::Usage)?),
+        "--fixers" => {
+          options.fixers = value()?
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(Error::Usage)?
         }
+        _ => return Err(Error::Usage(format!("unknown argument {}", arg))),
       }
     }
-    Ok(passport)
+    if !inputs.is_empty() {
+      options.inputs = inputs;
+    }
+    // Each of these decides what is done with the passports, --normalize and
+    // --filter write them out to --emit
+    let modes: Vec<&str> = [
+      ("--normalize", options.normalize),
+      ("--filter", options.filter.is_some()),
+      (
+        "--emit",
+        options.emit.is_some() && !options.normalize && options.filter.is_none(),
+      ),
+      ("--report", options.report),
+      ("--stats", options.stats),
+      ("--duplicates", options.duplicates),
+    ]
+    .iter()
+    .filter(|&&(_, given)| given)
+    .map(|&(mode, _)| mode)
+    .collect();
+    if modes.len() > 1 {
+      return Err(Error::Usage(format!(
+        "{} cannot be combined",
+        modes.join(" and ")
+      )));
+    }
+    Ok(options)
+  }
+
+  fn schema(&self) -> Result<Schema, Error> {
+    match &self.schema {
+      Some(path) => Schema::load(path),
+      None => Ok(PUZZLE_SCHEMA.clone()),
+    }
+  }
+
+  // Every passport in every input with the line it starts on
+  fn passports(&self) -> Result<Vec<(usize, Passport)>, Error> {
+    let mut passports = Vec::new();
+    for input in &self.inputs {
+      passports.extend(self.read(input)?);
+    }
+    Ok(passports)
+  }
+
+  // Where a passport was read, with the path when there are several inputs
+  fn place(&self, input: &Path, line: usize) -> String {
+    if self.inputs.len() > 1 {
+      format!("{}:{}", input.display(), line)
+    } else {
+      format!("line {}", line)
+    }
+  }
+
+  // Every passport in a file with the line it starts on, read in the chosen
+  // mode and format
+  fn read(&self, input: &Path) -> Result<Vec<(usize, Passport)>, Error> {
+    self.read_in(input, self.mode)
+  }
+
+  fn read_in(&self, input: &Path, mode: ParseMode) -> Result<Vec<(usize, Passport)>, Error> {
+    let batches: Vec<(usize, String)> = match self.input_format {
+      Format::Batch => {
+        return Ok(match mode {
+          ParseMode::Lenient => parse_newline_sep_numbered::<Passport>(input)?.collect(),
+          ParseMode::Strict => parse_newline_sep_numbered::<StrictPassport>(input)?
+            .map(|(line, StrictPassport(passport))| (line, passport))
+            .collect(),
+          ParseMode::Repair => parse_newline_sep_numbered::<RepairPassport>(input)?
+            .map(|(line, RepairPassport(passport))| (line, passport))
+            .collect(),
+        })
+      }
+      Format::Csv => csv::Reader::from_path(input)?
+        .deserialize()
+        .map(|record| record.map(|r: CsvRecord| (r.line, r.to_batch())))
+        .collect::<Result<_, _>>()?,
+      Format::JsonLines => io::BufReader::new(fs::File::open(input)?)
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| {
+          let record: JsonRecord = serde_json::from_str(&line?)?;
+          Ok((record.line, record.to_batch()))
+        })
+        .collect::<Result<_, Error>>()?,
+    };
+    Ok(
+      batches
+        .into_iter()
+        .filter_map(|(line, batch)| match Passport::parse(&batch, mode) {
+          Ok(passport) => Some((line, passport)),
+          Err(e) => {
+            eprintln!("{}: record from line {}: {}", input.display(), line, e);
+            None
+          }
+        })
+        .collect(),
+    )
   }
 }
 
-pub fn count_valid_passports() {
-  let p = Path::new("inputs/day4.txt");
-  if let Ok(passports) = parse_newline_sep::<Passport>(p) {
-    println!(
-      "Puzzle 1: {} valid passports",
-      passports.filter(|passport| passport.is_valid()).count()
-    );
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  #[error("{}: {1}", .0.display())]
+  Schema(PathBuf, String),
+  #[error(transparent)]
+  Csv(#[from] csv::Error),
+  #[error(transparent)]
+  Json(#[from] serde_json::Error),
+  #[error("{0}")]
+  Usage(String),
+}
+
+// Lists every invalid passport with its reasons, then how often each rule
+// was broken
+fn print_report(options: &Options) -> Result<(), Error> {
+  let schema = options.schema()?;
+  let mut total = 0;
+  let mut invalid = 0;
+  let mut rules: HashMap<String, usize> = HashMap::new();
+  for input in &options.inputs {
+    for (line, passport) in options.read(input)? {
+      total += 1;
+      let broken = schema.broken_rules(&passport);
+      if broken.is_empty() {
+        continue;
+      }
+      invalid += 1;
+      println!(
+        "{}: {}",
+        options.place(input, line),
+        schema.reasons(&passport).join(", ")
+      );
+      for rule in broken {
+        *rules.entry(rule).or_default() += 1;
+      }
+    }
+  }
+  println!("{} of {} passports are invalid", invalid, total);
+  for (rule, count) in by_count(rules) {
+    println!("{:>6} {}", count, rule);
+  }
+  Ok(())
+}
+
+fn write_passports<W: Write>(
+  mut writer: W,
+  passports: &[(usize, Passport)],
+  format: Format,
+  schema: &Schema,
+) -> Result<(), Error> {
+  match format {
+    Format::Batch => write_batch(writer, passports.iter().map(|(_, passport)| passport))?,
+    Format::Csv => {
+      let mut writer = csv::Writer::from_writer(writer);
+      for (line, passport) in passports {
+        writer.serialize(CsvRecord::new(*line, passport, schema))?;
+      }
+      writer.flush()?;
+    }
+    Format::JsonLines => {
+      for (line, passport) in passports {
+        serde_json::to_writer(&mut writer, &JsonRecord::new(*line, passport, schema))?;
+        writeln!(writer)?;
+      }
+      writer.flush()?;
+    }
+  }
+  Ok(())
+}
+
+fn emit_passports(
+  options: &Options,
+  path: &Path,
+  passports: &[(usize, Passport)],
+) -> Result<(), Error> {
+  let schema = options.schema()?;
+  if path == Path::new("-") {
+    write_passports(io::stdout().lock(), passports, options.format, &schema)
+  } else {
+    let file = BufWriter::new(fs::File::create(path)?);
+    write_passports(file, passports, options.format, &schema)
+  }
+}
+
+// Emits the fixed passports, and reports every change and how many
+// passports are valid before and after on stderr
+fn normalize_passports(options: &Options) -> Result<(), Error> {
+  let schema = options.schema()?;
+  let mut fixed = Vec::new();
+  let (mut valid_before, mut valid_after, mut changed) = (0, 0, 0);
+  // The pid fixer needs the digits that were split off as separate tokens
+  let mode = match options.mode {
+    ParseMode::Lenient => ParseMode::Repair,
+    mode => mode,
+  };
+  for input in &options.inputs {
+    for (line, passport) in options.read_in(input, mode)? {
+      let (passport_fixed, changes) =
+        normalize(&passport, &options.fixers, &schema, mode).map_err(Error::Usage)?;
+      if !changes.is_empty() {
+        changed += 1;
+        let changes: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+        eprintln!("{}: {}", options.place(input, line), changes.join(", "));
+      }
+      valid_before += schema.is_valid(&passport) as usize;
+      valid_after += schema.is_valid(&passport_fixed) as usize;
+      fixed.push((line, passport_fixed));
+    }
+  }
+  eprintln!(
+    "changed {} of {} passports, {} were valid and {} are now",
+    changed,
+    fixed.len(),
+    valid_before,
+    valid_after
+  );
+  let path = options.emit.clone().unwrap_or_else(|| PathBuf::from("-"));
+  emit_passports(options, &path, &fixed)
+}
+
+// Most common first, ties by name
+fn by_count(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+  let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+  counts.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+  counts
+}
+
+// A table for the terminal, columns of numbers are right aligned
+struct Table {
+  title: String,
+  header: Vec<String>,
+  rows: Vec<Vec<String>>,
+}
+
+impl Table {
+  fn new(title: &str, header: &[&str]) -> Self {
+    Table {
+      title: title.to_string(),
+      header: header.iter().map(|h| h.to_string()).collect(),
+      rows: Vec::new(),
+    }
+  }
+
+  fn row(&mut self, row: Vec<String>) {
+    self.rows.push(row);
+  }
+}
+
+impl fmt::Display for Table {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut widths: Vec<usize> = self.header.iter().map(|h| h.chars().count()).collect();
+    for row in &self.rows {
+      for (width, cell) in widths.iter_mut().zip(row) {
+        *width = (*width).max(cell.chars().count());
+      }
+    }
+    let numeric: Vec<bool> = (0..widths.len())
+      .map(|i| {
+        self.rows.iter().all(|row| {
+          row.get(i).is_some_and(|cell| {
+            cell
+              .chars()
+              .all(|c| c.is_ascii_digit() || c == '.' || c == '%' || c == '-')
+          })
+        })
+      })
+      .collect();
+    let line = |f: &mut fmt::Formatter, cells: &[String]| {
+      let cells: Vec<String> = cells
+        .iter()
+        .zip(&widths)
+        .zip(&numeric)
+        .map(|((cell, &width), &numeric)| match numeric {
+          true => format!("{:>width$}", cell, width = width),
+          false => format!("{:<width$}", cell, width = width),
+        })
+        .collect();
+      writeln!(f, "{}", cells.join("  ").trim_end())
+    };
+    writeln!(f, "{}", self.title)?;
+    line(f, &self.header)?;
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    line(f, &rule)?;
+    for row in &self.rows {
+      line(f, row)?;
+    }
+    Ok(())
+  }
+}
+
+// A bar of # as long as count is to max, out of 30
+fn bar(count: usize, max: usize) -> String {
+  "#".repeat((count * 30).checked_div(max).unwrap_or(0))
+}
+
+fn percent(count: usize, total: usize) -> String {
+  match total {
+    0 => "-".to_string(),
+    _ => format!("{:.1}%", count as f64 * 100.0 / total as f64),
   }
 }
 
-pub fn count_valid_passports_values() {
-  let p = Path::new("inputs/day4.txt");
-  if let Ok(passports) = parse_newline_sep::<Passport>(p) {
-    println!(
-      "Puzzle 2: {} valid passports",
+// Data quality tables for a batch of passports
+fn stats_tables(passports: &[Passport], schema: &Schema) -> Vec<Table> {
+  let total = passports.len();
+  let mut tables = Vec::new();
+
+  // How often each key is present, known keys first
+  let mut presence = Table::new("Field presence", &["field", "present", "rate"]);
+  let mut unknown: HashMap<String, usize> = HashMap::new();
+  for passport in passports {
+    for (key, _) in &passport.fields {
+      if !KNOWN_KEYS.contains(&key.as_str()) {
+        *unknown.entry(key.clone()).or_default() += 1;
+      }
+    }
+  }
+  let known = KNOWN_KEYS.iter().map(|&key| {
+    let count = passports
+      .iter()
+      .filter(|passport| passport.value(key).is_some())
+      .count();
+    (key.to_string(), count)
+  });
+  for (key, count) in known.chain(by_count(unknown)) {
+    presence.row(vec![key, count.to_string(), percent(count, total)]);
+  }
+  tables.push(presence);
+
+  // Years by decade
+  let mut decades: BTreeMap<u32, [usize; 3]> = BTreeMap::new();
+  for passport in passports {
+    for (i, year) in [passport.byr, passport.iyr, passport.eyr]
+      .iter()
+      .enumerate()
+    {
+      if let Some(Year(year)) = year {
+        decades.entry(year / 10 * 10).or_default()[i] += 1;
+      }
+    }
+  }
+  let mut years = Table::new("Years by decade", &["decade", "byr", "iyr", "eyr"]);
+  for (decade, counts) in decades {
+    let mut row = vec![format!("{}s", decade)];
+    row.extend(counts.iter().map(|count| count.to_string()));
+    years.row(row);
+  }
+  tables.push(years);
+
+  // Heights by unit, in buckets of 10cm, 5in, 100mm or a foot
+  let mut heights: BTreeMap<(&str, u32), usize> = BTreeMap::new();
+  for hgt in passports.iter().filter_map(|passport| passport.hgt) {
+    let bucket = match hgt {
+      Height::Cm(cm) => ("cm", cm / 10 * 10),
+      Height::In(inches) => ("in", inches / 5 * 5),
+      Height::Mm(mm) => ("mm", mm / 100 * 100),
+      Height::FtIn(ft, _) => ("ft", ft),
+    };
+    *heights.entry(bucket).or_default() += 1;
+  }
+  let unparsed = passports
+    .iter()
+    .filter(|passport| passport.hgt.is_none() && passport.value("hgt").is_some())
+    .count();
+  let max = heights.values().copied().max().unwrap_or(0).max(unparsed);
+  let mut histogram = Table::new("Heights", &["height", "count", ""]);
+  for ((unit, from), count) in heights {
+    let width = match unit {
+      "cm" => 10,
+      "in" => 5,
+      "mm" => 100,
+      _ => 1,
+    };
+    let label = match unit {
+      "ft" => format!("{}ft", from),
+      _ => format!("{}-{}{}", from, from + width - 1, unit),
+    };
+    histogram.row(vec![label, count.to_string(), bar(count, max)]);
+  }
+  if unparsed > 0 {
+    histogram.row(vec![
+      "unparsed".to_string(),
+      unparsed.to_string(),
+      bar(unparsed, max),
+    ]);
+  }
+  tables.push(histogram);
+
+  // Eye colours, including values that are not a colour
+  let mut colors: HashMap<String, usize> = HashMap::new();
+  for passport in passports {
+    let color = match (passport.ecl, passport.value("ecl")) {
+      (Some(ecl), _) => ecl.to_string(),
+      (None, Some(_)) => "other".to_string(),
+      (None, None) => continue,
+    };
+    *colors.entry(color).or_default() += 1;
+  }
+  let mut eyes = Table::new("Eye colours", &["ecl", "count", "rate"]);
+  for (color, count) in by_count(colors) {
+    eyes.row(vec![color, count.to_string(), percent(count, total)]);
+  }
+  tables.push(eyes);
+
+  // Passports breaking each rule, however often each one breaks it
+  let mut rules: HashMap<String, usize> = HashMap::new();
+  for passport in passports {
+    let mut broken = schema.broken_rules(passport);
+    broken.sort();
+    broken.dedup();
+    for rule in broken {
+      *rules.entry(rule).or_default() += 1;
+    }
+  }
+  let mut failures = Table::new("Most common failures", &["rule", "passports", "rate"]);
+  for (rule, count) in by_count(rules).into_iter().take(10) {
+    failures.row(vec![rule, count.to_string(), percent(count, total)]);
+  }
+  tables.push(failures);
+
+  tables
+}
+
+// Passports that share a pid or cid
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct DuplicateGroup {
+  key: &'static str,
+  value: String,
+  // Indexes into the passports searched
+  members: Vec<usize>,
+  // Keys whose values are not the same in every member, empty for exact
+  // duplicates
+  differing: Vec<String>,
+}
+
+fn find_duplicates(passports: &[&Passport]) -> Vec<DuplicateGroup> {
+  let mut groups = Vec::new();
+  for &key in &["pid", "cid"] {
+    let mut by_value: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, passport) in passports.iter().enumerate() {
+      if let Some(value) = passport.value(key) {
+        by_value.entry(value).or_default().push(i);
+      }
+    }
+    for (value, members) in by_value.into_iter().filter(|(_, m)| m.len() > 1) {
+      let mut keys: Vec<&str> = Vec::new();
+      for &i in &members {
+        for (k, _) in &passports[i].fields {
+          if !keys.contains(&k.as_str()) {
+            keys.push(k);
+          }
+        }
+      }
+      let first = passports[members[0]];
+      let differing = keys
+        .into_iter()
+        .filter(|&k| {
+          members
+            .iter()
+            .any(|&i| passports[i].value(k) != first.value(k))
+        })
+        .map(str::to_string)
+        .collect();
+      groups.push(DuplicateGroup {
+        key,
+        value: value.to_string(),
+        members,
+        differing,
+      });
+    }
+  }
+  groups
+}
+
+// Prints or emits the passports the filter selects
+fn filter_passports(options: &Options, filter: &Filter) -> Result<(), Error> {
+  let schema = options.schema()?;
+  let mut selected: Vec<(usize, Passport)> = Vec::new();
+  let mut total = 0;
+  for input in &options.inputs {
+    let passports = options.read(input)?;
+    let count = passports.len();
+    let before = selected.len();
+    selected.extend(
       passports
-        .filter(|passport| passport.is_valid_values())
-        .count()
+        .into_iter()
+        .filter(|(_, passport)| filter.matches(passport, &schema)),
     );
+    if options.inputs.len() > 1 {
+      eprintln!(
+        "{}: {} of {} passports match",
+        input.display(),
+        selected.len() - before,
+        count
+      );
+    }
+    total += count;
+  }
+  eprintln!("{} of {} passports match", selected.len(), total);
+  let path = options.emit.clone().unwrap_or_else(|| PathBuf::from("-"));
+  emit_passports(options, &path, &selected)
+}
+
+// Lists every group of passports sharing a pid or cid across all inputs
+fn print_duplicates(options: &Options) -> Result<(), Error> {
+  let mut located = Vec::new();
+  for input in &options.inputs {
+    let name = input.display().to_string();
+    for (line, passport) in options.read(input)? {
+      located.push((format!("{}:{}", name, line), passport));
+    }
+  }
+  let passports: Vec<&Passport> = located.iter().map(|(_, passport)| passport).collect();
+  let groups = find_duplicates(&passports);
+  for group in &groups {
+    let places: Vec<&str> = group
+      .members
+      .iter()
+      .map(|&i| located[i].0.as_str())
+      .collect();
+    if group.differing.is_empty() {
+      println!(
+        "{} {}: exact duplicates at {}",
+        group.key,
+        group.value,
+        places.join(", ")
+      );
+    } else {
+      println!(
+        "{} {}: conflicting at {}, differing {}",
+        group.key,
+        group.value,
+        places.join(", "),
+        group.differing.join(", ")
+      );
+    }
+  }
+  let exact = groups.iter().filter(|g| g.differing.is_empty()).count();
+  println!(
+    "{} exact and {} conflicting groups in {} passports",
+    exact,
+    groups.len() - exact,
+    located.len()
+  );
+  Ok(())
+}
+
+fn print_stats(options: &Options) -> Result<(), Error> {
+  let schema = options.schema()?;
+  let passports: Vec<Passport> = options
+    .passports()?
+    .into_iter()
+    .map(|(_, passport)| passport)
+    .collect();
+  let valid = passports
+    .iter()
+    .filter(|passport| schema.is_valid(passport))
+    .count();
+  println!(
+    "{} passports, {} valid ({})",
+    passports.len(),
+    valid,
+    percent(valid, passports.len())
+  );
+  for table in stats_tables(&passports, &schema) {
+    println!();
+    print!("{}", table);
+  }
+  Ok(())
+}
+
+pub fn day_4(options: &Options) -> Result<(), Error> {
+  if options.normalize {
+    normalize_passports(options)
+  } else if let Some(filter) = &options.filter {
+    filter_passports(options, filter)
+  } else if let Some(path) = &options.emit {
+    emit_passports(options, path, &options.passports()?)
+  } else if options.report {
+    print_report(options)
+  } else if options.stats {
+    print_stats(options)
+  } else if options.duplicates {
+    print_duplicates(options)
+  } else {
+    let mut passports = Vec::new();
+    for input in &options.inputs {
+      for (line, passport) in options.read(input)? {
+        if !passport.diagnostics.is_empty() {
+          let reasons: Vec<String> = passport.diagnostics.iter().map(|d| d.to_string()).collect();
+          eprintln!("{}: {}", options.place(input, line), reasons.join(", "));
+        }
+        passports.push(passport);
+      }
+    }
+    count_valid_passports(&passports);
+    count_valid_passports_values(&options.schema()?, &passports);
+    Ok(())
   }
 }
 
+fn count_valid_passports(passports: &[Passport]) {
+  println!(
+    "Puzzle 1: {} valid passports",
+    passports
+      .iter()
+      .filter(|passport| passport.is_valid())
+      .count()
+  );
+}
+
+fn count_valid_passports_values(schema: &Schema, passports: &[Passport]) {
+  println!(
+    "Puzzle 2: {} valid passports",
+    passports
+      .iter()
+      .filter(|passport| schema.is_valid(passport))
+      .count()
+  );
+}
+
 #[test]
 fn creates_a_valid_passport_1() {
   let passport = Passport::from_str(
@@ -161,7 +2231,472 @@ iyr:2011 ecl:brn hgt:59in",
   assert_eq!(passport.is_valid(), false);
 }
 
+#[test]
+fn bad_records_are_skipped() {
+  let options = Options {
+    inputs: vec![PathBuf::from("inputs/day4_bad_record_test.txt")],
+    ..Options::default()
+  };
+  let passports = options.passports().unwrap();
+  let lines: Vec<usize> = passports.iter().map(|(line, _)| *line).collect();
+  assert_eq!(lines, vec![1, 8]);
+  assert!(passports.iter().all(|(_, passport)| passport.is_valid()));
+}
+
+#[test]
+fn places_name_the_input_when_there_are_several() {
+  let mut options = Options::default();
+  let input = PathBuf::from("inputs/day4_test.txt");
+  assert_eq!(options.place(&input, 3), "line 3");
+  options.inputs.push(input.clone());
+  assert_eq!(options.place(&input, 3), "inputs/day4_test.txt:3");
+}
+
 // #[test]
 // fn count_valid_passports_expect() {
 //   assert_eq!(read_from_file("inputs/day4_test.txt"), 2);
 // }
+
+#[test]
+fn puzzle_schema_validates_values() {
+  let valid = Passport::from_str(
+    "pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980
+hcl:#623a2f",
+  )
+  .unwrap();
+  assert!(PUZZLE_SCHEMA.is_valid(&valid));
+  for invalid in &[
+    "eyr:1972 cid:100 hcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926",
+    "hcl:dab227 iyr:2012 ecl:brn hgt:182cm pid:021572410 eyr:2020 byr:1992 cid:277",
+    "hgt:59cm ecl:zzz eyr:2038 hcl:74454a iyr:2023 pid:3556412378 byr:2007",
+  ] {
+    let passport = Passport::from_str(invalid).unwrap();
+    assert!(!PUZZLE_SCHEMA.is_valid(&passport));
+  }
+}
+
+#[test]
+fn schema_from_config() {
+  let schema = Schema::from_str(
+    r#"
+[[field]]
+name = "hgt"
+required = true
+type = "measure"
+units = { cm = [100, 120] }
+
+[[field]]
+name = "ecl"
+one_of = ["red"]
+"#,
+  )
+  .unwrap();
+  let passport = |s| Passport::from_str(s).unwrap();
+  assert!(schema.is_valid(&passport("hgt:110cm")));
+  assert!(schema.is_valid(&passport("hgt:110cm ecl:red")));
+  assert!(!schema.is_valid(&passport("hgt:110cm ecl:blu")));
+  assert!(!schema.is_valid(&passport("hgt:110in")));
+  assert!(!schema.is_valid(&passport("ecl:red")));
+  assert!(Schema::from_str("[[field]]\nname = \"x\"\nregex = \"(\"").is_err());
+  assert!(Schema::from_str("[[field]]\nname = \"x\"\nmax = 3").is_err());
+  assert!(Schema::from_str("[[field]]\nname = \"x\"\ntype = \"height\"\nrange = [1, 2]").is_err());
+  assert!(
+    Schema::from_str("[[field]]\nname = \"x\"\ntype = \"height\"\nunits = { ft = [4, 7] }")
+      .is_err()
+  );
+}
+
+#[test]
+fn schema_checks_typed_values() {
+  let violations = |s| {
+    let passport = Passport::from_str(s).unwrap();
+    let violations: Vec<String> = PUZZLE_SCHEMA
+      .validate(&passport)
+      .iter()
+      .filter(|v| v.field() != "byr" && v.field() != "iyr" && v.field() != "eyr")
+      .map(|v| v.to_string())
+      .collect();
+    violations
+  };
+  // Heights in any unit are converted before comparing
+  assert!(violations("hgt:5ft11in hcl:#623a2f ecl:grn pid:087499704").is_empty());
+  assert!(violations("hgt:1800mm hcl:#623a2f ecl:grn pid:087499704").is_empty());
+  assert_eq!(
+    violations("hgt:7ft0in hcl:#623A2F ecl:GRN pid:08749970a"),
+    vec![
+      "hgt 213cm out of range 150cm..=193cm",
+      "hcl [#623A2F] is malformed",
+      "ecl [GRN] is not allowed",
+      "pid [08749970a] is malformed",
+    ]
+  );
+  assert_eq!(
+    violations("hgt:194cm hcl:#623a2f ecl:grn pid:87499704"),
+    vec![
+      "hgt 194cm out of range 150cm..=193cm",
+      "pid [87499704] has 8 characters, expected 9",
+    ]
+  );
+}
+
+#[test]
+fn validate_lists_every_violation() {
+  let passport =
+    Passport::from_str("eyr:1972 cid:100 hcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926")
+      .unwrap();
+  let violations = PUZZLE_SCHEMA.validate(&passport);
+  let reasons: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+  assert_eq!(
+    reasons,
+    vec![
+      "eyr 1972 out of range 2020..=2030",
+      "hgt [170] has an unknown unit",
+      "pid [186cm] is malformed",
+      "pid [186cm] has 5 characters, expected 9",
+    ]
+  );
+  assert_eq!(violations[1].rule(), "hgt unknown unit");
+
+  let passport = Passport::from_str("hgt:190in ecl:zzz").unwrap();
+  let violations = PUZZLE_SCHEMA.validate(&passport);
+  assert!(violations.contains(&Violation::Missing {
+    field: "byr".to_string()
+  }));
+  assert!(violations.contains(&Violation::OutOfRange {
+    field: "hgt".to_string(),
+    value: 190,
+    unit: Some("in".to_string()),
+    min: 59,
+    max: 76,
+  }));
+  assert_eq!(violations.last().unwrap().to_string(), "missing pid");
+}
+
+#[test]
+fn passports_round_trip() {
+  let passport = Passport::from_str("hgt:170 cid:100 xyz:1\nbyr:1926\n\n").unwrap();
+  assert!(passport.hgt.is_none());
+  assert_eq!(passport.value("hgt"), Some("170"));
+  assert_eq!(passport.value("xyz"), Some("1"));
+  assert_eq!(passport.to_string(), "hgt:170 cid:100 xyz:1\nbyr:1926");
+
+  let input = fs::read_to_string("inputs/day4_test.txt").unwrap();
+  let passports: Vec<Passport> = super::utils::parse_newline_sep(Path::new("inputs/day4_test.txt"))
+    .unwrap()
+    .collect();
+  let mut output = Vec::new();
+  write_batch(&mut output, &passports).unwrap();
+  assert_eq!(
+    String::from_utf8(output).unwrap(),
+    format!("{}\n", input.trim_end())
+  );
+}
+
+#[test]
+fn strict_mode_reports_diagnostics() {
+  let input = "byr:abc hgt:170 byr:1980 foo:1 hcl:\npid:1:2";
+  assert!(Passport::from_str(input).is_err());
+  let passport = Passport::parse("byr:1980 byr:1990 foo:1", ParseMode::Lenient).unwrap();
+  assert!(passport.diagnostics.is_empty());
+  assert_eq!(passport.byr, Some(Year(1990)));
+
+  let passport = Passport::parse(input, ParseMode::Strict).unwrap();
+  let reasons: Vec<String> = passport.diagnostics.iter().map(|d| d.to_string()).collect();
+  assert_eq!(
+    reasons,
+    vec![
+      "byr [abc] has the wrong type",
+      "hgt [170] has the wrong type",
+      "duplicate byr",
+      "unknown key foo",
+      "hcl is empty",
+      "[pid:1:2] is not a key:value pair",
+    ]
+  );
+  assert_eq!(passport.byr, Some(Year(1980)));
+  assert_eq!(passport.diagnostics[2].rule(), "byr duplicate");
+  assert!(!passport.is_valid());
+  assert_eq!(passport.to_string(), input);
+
+  // Diagnostics do not change which passports puzzle 1 counts
+  let passport = Passport::parse(
+    "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd\nbyr:1937 iyr:2017 ? hgt:183cm foo:1",
+    ParseMode::Strict,
+  )
+  .unwrap();
+  assert_eq!(passport.diagnostics.len(), 2);
+  assert!(passport.is_valid());
+  assert_eq!(
+    passport.to_string(),
+    "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd\nbyr:1937 iyr:2017 ? hgt:183cm foo:1"
+  );
+}
+
+#[test]
+fn export_and_import_round_trip() {
+  let passports: Vec<(usize, Passport)> = vec![
+    (
+      1,
+      Passport::from_str("hgt:170 cid:100 xyz:1\nbyr:1926").unwrap(),
+    ),
+    (
+      4,
+      Passport::from_str("pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980\nhcl:#623a2f")
+        .unwrap(),
+    ),
+  ];
+  let mut csv = Vec::new();
+  write_passports(&mut csv, &passports, Format::Csv, &PUZZLE_SCHEMA).unwrap();
+  let csv = String::from_utf8(csv).unwrap();
+  let mut rows = csv.lines();
+  assert_eq!(
+    rows.next(),
+    Some("line,byr,iyr,eyr,hgt,hcl,ecl,pid,cid,other,height_cm,height_in,complete,valid,reasons")
+  );
+  assert_eq!(
+    rows.next(),
+    Some("1,1926,,,170,,,,100,xyz:1,,,false,false,missing iyr; missing eyr; hgt [170] has an unknown unit; missing hcl; missing ecl; missing pid")
+  );
+  assert_eq!(
+    rows.next(),
+    Some("4,1980,2012,2030,74in,#623a2f,grn,087499704,,,187.96,74.0,true,true,")
+  );
+  let records: Vec<CsvRecord> = csv::Reader::from_reader(csv.as_bytes())
+    .deserialize()
+    .collect::<Result<_, _>>()
+    .unwrap();
+  assert_eq!(records[0].to_batch(), "byr:1926 hgt:170 cid:100 xyz:1");
+  assert_eq!(records[0].other.as_deref(), Some("xyz:1"));
+  assert!(!records[0].valid);
+  assert!(records[1].valid);
+
+  let mut json = Vec::new();
+  write_passports(&mut json, &passports, Format::JsonLines, &PUZZLE_SCHEMA).unwrap();
+  let json = String::from_utf8(json).unwrap();
+  let record: JsonRecord = serde_json::from_str(json.lines().next().unwrap()).unwrap();
+  assert_eq!(record.line, 1);
+  assert_eq!(record.to_batch(), "hgt:170 cid:100 xyz:1\nbyr:1926");
+  assert!(record
+    .reasons
+    .contains(&"hgt [170] has an unknown unit".to_string()));
+}
+
+#[test]
+fn typed_fields() {
+  let passport =
+    Passport::from_str("byr:1937 hgt:5ft11in hcl:#FFfD0a ecl:gry pid:000033327").unwrap();
+  assert_eq!(passport.byr, Some(Year(1937)));
+  assert_eq!(passport.hgt, Some(Height::FtIn(5, 11)));
+  assert_eq!(
+    passport.hcl,
+    Some(HairColor {
+      r: 255,
+      g: 253,
+      b: 10
+    })
+  );
+  assert_eq!(passport.ecl, Some(EyeColor::Gry));
+  assert_eq!(passport.pid.as_ref().unwrap().to_string(), "000033327");
+
+  assert_eq!("183cm".parse::<Height>().unwrap().inches().round(), 72.0);
+  assert_eq!("1830mm".parse::<Height>().unwrap().centimetres(), 183.0);
+  assert_eq!("72in".parse::<Height>().unwrap(), Height::In(72));
+  assert_eq!("6ft0in".parse::<Height>().unwrap().centimetres(), 182.88);
+  assert_eq!(Height::FtIn(5, 11).inches(), 71.0);
+  assert!("183".parse::<Height>().is_err());
+  assert_eq!(
+    "#FFfD0a".parse::<HairColor>().unwrap().to_string(),
+    "#fffd0a"
+  );
+  assert!("#fffd0".parse::<HairColor>().is_err());
+  assert!("123abc".parse::<HairColor>().is_err());
+  assert_eq!(EyeColor::Hzl.to_string(), "hzl");
+  assert!("xyz".parse::<EyeColor>().is_err());
+  assert!("12a".parse::<PassportId>().is_err());
+}
+
+#[test]
+fn normalize_fixes_common_mistakes() {
+  let passport = Passport::parse(
+    "hcl:#ABC123 hgt:180\npid:012 345 678-9 ecl:brn",
+    ParseMode::Strict,
+  )
+  .unwrap();
+  let (fixed, changes) = normalize(&passport, &FIXERS, &PUZZLE_SCHEMA, ParseMode::Strict).unwrap();
+  let changes: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+  assert_eq!(
+    changes,
+    vec![
+      "hcl [#ABC123] -> [#abc123]",
+      "hgt [180] -> [180cm]",
+      "pid [012] -> [0123456789]",
+    ]
+  );
+  assert_eq!(
+    fixed.to_string(),
+    "hcl:#abc123 hgt:180cm\npid:0123456789 ecl:brn"
+  );
+  assert!(fixed.diagnostics.is_empty());
+  assert_eq!(fixed.hgt, Some(Height::Cm(180)));
+
+  // 60 only fits in, but the fixer is not enabled
+  let passport = Passport::from_str("hgt:60 hcl:#abc123").unwrap();
+  let (_, changes) = normalize(
+    &passport,
+    &[Fixer::HexCase],
+    &PUZZLE_SCHEMA,
+    ParseMode::Lenient,
+  )
+  .unwrap();
+  assert!(changes.is_empty());
+  let (fixed, _) = normalize(&passport, &FIXERS, &PUZZLE_SCHEMA, ParseMode::Lenient).unwrap();
+  assert_eq!(fixed.value("hgt"), Some("60in"));
+  let passport = Passport::from_str("hgt:100").unwrap();
+  let (_, changes) = normalize(&passport, &FIXERS, &PUZZLE_SCHEMA, ParseMode::Lenient).unwrap();
+  assert!(changes.is_empty());
+
+  // Only the parts right after the pid are joined
+  let batch = "99 pid:012 345\n678 x 901 ecl:brn";
+  assert_eq!(Passport::from_str(batch).unwrap_err(), "field incorrect 99");
+  let passport = Passport::parse(batch, ParseMode::Repair).unwrap();
+  let (fixed, changes) = normalize(
+    &passport,
+    &[Fixer::PidDigits],
+    &PUZZLE_SCHEMA,
+    ParseMode::Repair,
+  )
+  .unwrap();
+  assert_eq!(changes[0].to_string(), "pid [012] -> [012345678]");
+  assert_eq!(fixed.to_string(), "99 pid:012345678\nx 901 ecl:brn");
+}
+
+#[test]
+fn stats_tables_summarize_a_batch() {
+  let passports: Vec<Passport> = [
+    "byr:1937 iyr:2017 hgt:183cm ecl:gry",
+    "byr:1931 hgt:179cm ecl:gry foo:1",
+    "byr:2002 hgt:70in ecl:zzz",
+    "hgt:170",
+  ]
+  .iter()
+  .map(|s| Passport::from_str(s).unwrap())
+  .collect();
+  let tables: Vec<String> = stats_tables(&passports, &PUZZLE_SCHEMA)
+    .iter()
+    .map(|table| table.to_string())
+    .collect();
+  assert!(tables[0].contains("\nbyr          3   75.0%\n"));
+  assert!(tables[0].ends_with("\nfoo          1   25.0%\n"));
+  assert_eq!(
+    tables[1],
+    "Years by decade
+decade  byr  iyr  eyr
+------  ---  ---  ---
+1930s     2    0    0
+2000s     1    0    0
+2010s     0    1    0
+"
+  );
+  assert!(tables[2].contains("\n170-179cm      1  ##############################\n"));
+  assert!(tables[2].ends_with("\nunparsed       1  ##############################\n"));
+  assert!(tables[3].contains("\ngry        2  50.0%\n"));
+  assert!(tables[3].contains("\nother      1  25.0%\n"));
+  assert!(tables[4].starts_with("Most common failures\nrule              passports    rate\n"));
+  assert!(tables[4].contains("\npid missing               4  100.0%\n"));
+
+  // A rule broken twice by one passport counts it once
+  let passport = Passport::parse("cid:1 cid:2 cid:3", ParseMode::Strict).unwrap();
+  let failures = stats_tables(&[passport], &PUZZLE_SCHEMA)[4].to_string();
+  assert!(failures.lines().any(|line| line
+    .split_whitespace()
+    .eq(["cid", "duplicate", "1", "100.0%"].iter().copied())));
+}
+
+#[test]
+fn duplicates_are_exact_or_conflicting() {
+  let passports: Vec<Passport> = [
+    "pid:087499704 ecl:grn\nbyr:1980",
+    "byr:1980 ecl:grn pid:087499704",
+    "pid:087499704 ecl:blu byr:1980 cid:1",
+    "pid:123 cid:2",
+    "pid:456 cid:2",
+  ]
+  .iter()
+  .map(|s| Passport::from_str(s).unwrap())
+  .collect();
+  let refs: Vec<&Passport> = passports.iter().collect();
+  let groups = find_duplicates(&refs);
+  assert_eq!(groups.len(), 2);
+  assert_eq!(groups[0].key, "pid");
+  assert_eq!(groups[0].members, vec![0, 1, 2]);
+  assert_eq!(groups[0].differing, vec!["ecl", "cid"]);
+  assert_eq!(groups[1].key, "cid");
+  assert_eq!(groups[1].differing, vec!["pid"]);
+
+  let groups = find_duplicates(&refs[..2]);
+  assert_eq!(groups.len(), 1);
+  assert!(groups[0].differing.is_empty());
+}
+
+#[test]
+fn filters_select_passports() {
+  let passport = |s| Passport::from_str(s).unwrap();
+  let filter = |s: &str| Filter::from_str(s).unwrap();
+  let tall = passport("ecl:blu hgt:71in byr:2010");
+  let short = passport("ecl:grn hgt:160cm");
+  let valid = passport("pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980 hcl:#623a2f");
+
+  let query = filter("ecl in (blu,grn) and hgt.cm >= 170 and not valid");
+  assert!(query.matches(&tall, &PUZZLE_SCHEMA));
+  assert!(!query.matches(&short, &PUZZLE_SCHEMA));
+  assert!(!query.matches(&valid, &PUZZLE_SCHEMA));
+
+  assert!(filter("valid or hgt < 170").matches(&valid, &PUZZLE_SCHEMA));
+  assert!(filter("byr > 2000 and not (pid or cid)").matches(&tall, &PUZZLE_SCHEMA));
+  assert!(filter("hcl = '#623a2f'").matches(&valid, &PUZZLE_SCHEMA));
+  assert!(!filter("byr != 1980").matches(&valid, &PUZZLE_SCHEMA));
+  assert!(!filter("hgt.in > 70").matches(&short, &PUZZLE_SCHEMA));
+  assert_eq!(
+    filter("not valid or complete and pid"),
+    Filter::Or(
+      Box::new(Filter::Not(Box::new(Filter::Valid))),
+      Box::new(Filter::And(
+        Box::new(Filter::Complete),
+        Box::new(Filter::Present(Operand::Field("pid".to_string())))
+      ))
+    )
+  );
+  for invalid in &[
+    "",
+    "ecl in blu",
+    "(valid",
+    "valid valid",
+    "hgt.ft > 5",
+    "byr >",
+    "and",
+    "ecl = 'blu",
+    "ecl = \"",
+  ] {
+    assert!(Filter::from_str(invalid).is_err(), "{}", invalid);
+  }
+}
+
+#[test]
+fn options_reject_conflicting_modes() {
+  let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+  for given in &[
+    &["--normalize", "--emit", "out.txt"][..],
+    &["--filter", "valid", "--emit", "-"],
+    &["--report", "--schema", "schema.toml"],
+  ] {
+    assert!(Options::from_args(&args(given)).is_ok());
+  }
+  for rejected in &[
+    &["--filter", "valid", "--report"][..],
+    &["--report", "--stats"],
+    &["--emit", "-", "--duplicates"],
+    &["--normalize", "--filter", "valid"],
+  ] {
+    assert!(Options::from_args(&args(rejected)).is_err());
+  }
+}
@@ -1,12 +1,11 @@
-use super::utils::parse_singleline_sep;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::path::Path;
+use super::utils::parse_number;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-
-lazy_static! {
-  static ref SEAT_RE: Regex = Regex::new(r"^([F|B]{7})([L|R]{3})$").unwrap();
-}
+use thiserror::Error;
 
 #[derive(Debug, Default, Eq, PartialEq)]
 struct Seat {
@@ -19,60 +18,623 @@ impl FromStr for Seat {
   type Err = String;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let mut seat = Seat::default();
-    let caps = SEAT_RE
-      .captures(s.trim())
-      .ok_or_else(|| format!("No regex match for [{}]", s))?;
-
-    let front_back = String::from(
-      caps
-        .get(1)
-        .ok_or_else(|| format!("No front/back for [{}]", s))?
-        .as_str(),
-    )
-    .replace("F", "0")
-    .replace("B", "1");
-    let left_right = String::from(
-      caps
-        .get(2)
-        .ok_or_else(|| format!("No left/right for [{}]", s))?
-        .as_str(),
+    Codec::default().decode(s).map_err(|e| e.to_string())
+  }
+}
+
+// Why a boarding pass could not be decoded
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum PassError {
+  #[error("[{pass}] has {found} letters, expected {expected}")]
+  Length {
+    pass: String,
+    found: usize,
+    expected: usize,
+  },
+  // position counts from 1
+  #[error("[{pass}] has {letter:?} at position {position}, expected {expected}")]
+  Letter {
+    pass: String,
+    position: usize,
+    letter: char,
+    expected: String,
+  },
+}
+
+// How a boarding pass encodes a seat: the row then the column as binary
+// space partitions, each letter picking the lower or upper half
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Codec {
+  row_bits: u32,
+  column_bits: u32,
+  // The letters for the lower and upper half
+  row_letters: (char, char),
+  column_letters: (char, char),
+}
+
+impl Default for Codec {
+  fn default() -> Self {
+    Codec {
+      row_bits: 7,
+      column_bits: 3,
+      row_letters: ('F', 'B'),
+      column_letters: ('L', 'R'),
+    }
+  }
+}
+
+// The seat map and occupancy hold every seat id, so ids are kept to 20 bits
+const MAX_ID_BITS: u32 = 20;
+
+impl Codec {
+  fn check(&self) -> Result<(), String> {
+    let bits = self.row_bits.checked_add(self.column_bits);
+    if self.row_bits == 0 || self.column_bits == 0 || bits.is_none_or(|bits| bits > MAX_ID_BITS) {
+      return Err(format!(
+        "{} row and {} column letters do not fit a seat id of at most {} letters",
+        self.row_bits, self.column_bits, MAX_ID_BITS
+      ));
+    }
+    let (lower, upper) = self.row_letters;
+    let (left, right) = self.column_letters;
+    if lower == upper || left == right {
+      return Err("the letters for each half must differ".to_string());
+    }
+    Ok(())
+  }
+
+  fn columns(&self) -> u32 {
+    1 << self.column_bits
+  }
+
+  fn id(&self, row: u32, column: u32) -> u32 {
+    row * self.columns() + column
+  }
+
+  fn seat(&self, id: u32) -> Seat {
+    Seat {
+      row: id / self.columns(),
+      column: id % self.columns(),
+      id,
+    }
+  }
+
+  fn decode(&self, s: &str) -> Result<Seat, PassError> {
+    let s = s.trim();
+    let letters: Vec<char> = s.chars().collect();
+    let expected = (self.row_bits + self.column_bits) as usize;
+    if letters.len() != expected {
+      return Err(PassError::Length {
+        pass: s.to_string(),
+        found: letters.len(),
+        expected,
+      });
+    }
+    let (rows, columns) = letters.split_at(self.row_bits as usize);
+    let number = |letters: &[char], offset: usize, (zero, one): (char, char)| {
+      letters
+        .iter()
+        .enumerate()
+        .try_fold(0, |n, (i, &c)| match c {
+          c if c == zero => Ok(n << 1),
+          c if c == one => Ok(n << 1 | 1),
+          _ => Err(PassError::Letter {
+            pass: s.to_string(),
+            position: offset + i + 1,
+            letter: c,
+            expected: format!("{} or {}", zero, one),
+          }),
+        })
+    };
+    let row = number(rows, 0, self.row_letters)?;
+    let column = number(columns, rows.len(), self.column_letters)?;
+    Ok(Seat {
+      row,
+      column,
+      id: self.id(row, column),
+    })
+  }
+
+  // The seat at a row and column, checked before the id is worked out
+  fn seat_at(&self, row: u32, column: u32) -> Result<Seat, String> {
+    if row >> self.row_bits != 0 || column >> self.column_bits != 0 {
+      return Err(format!(
+        "row {} column {} is not on this aircraft",
+        row, column
+      ));
+    }
+    Ok(Seat {
+      row,
+      column,
+      id: self.id(row, column),
+    })
+  }
+
+  fn encode(&self, seat: &Seat) -> Result<String, String> {
+    self.seat_at(seat.row, seat.column)?;
+    let letters = |n: u32, bits: u32, (zero, one): (char, char)| {
+      (0..bits)
+        .rev()
+        .map(move |bit| if n >> bit & 1 == 0 { zero } else { one })
+    };
+    Ok(
+      letters(seat.row, self.row_bits, self.row_letters)
+        .chain(letters(seat.column, self.column_bits, self.column_letters))
+        .collect(),
     )
-    .replace("L", "0")
-    .replace("R", "1");
-    seat.row = u32::from_str_radix(front_back.as_str(), 2).unwrap_or_default();
-    seat.column = u32::from_str_radix(left_right.as_str(), 2).unwrap_or_default();
-    seat.id = seat.row * 8 + seat.column;
-    Ok(seat)
   }
 }
 
-pub fn day_5_puzzle_1() {
-  let p = Path::new("inputs/day5.txt");
-  if let Ok(seats) = parse_singleline_sep::<Seat>(p) {
-    println!(
-      "Puzzle 1: max id is {}",
-      seats.map(|s| s.id).max().unwrap_or_default()
+#[derive(Debug, Clone)]
+pub struct Options {
+  input: PathBuf,
+  codec: Codec,
+  // Print the boarding pass for a row and column instead
+  encode: Option<(u32, u32)>,
+  // Draw the seats instead of solving the puzzles
+  map: bool,
+  // Seat groups of these sizes instead of solving the puzzles
+  groups: Vec<usize>,
+  // Columns with an aisle just before them
+  aisles: Option<Vec<u32>>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      input: PathBuf::from("inputs/day5.txt"),
+      codec: Codec::default(),
+      encode: None,
+      map: false,
+      groups: Vec::new(),
+      aisles: None,
+    }
+  }
+}
+
+impl Options {
+  pub fn from_args(args: &[String]) -> Result<Self, Error> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| Error::Usage(format!("{} expects a value", arg)))
+      };
+      match arg.as_str() {
+        "--input" => options.input = PathBuf::from(value()?),
+        "--row-bits" => {
+          options.codec.row_bits = parse_number(arg, value()?).map_err(Error::Usage)?
+        }
+        "--column-bits" => {
+          options.codec.column_bits = parse_number(arg, value()?).map_err(Error::Usage)?
+        }
+        "--row-letters" => options.codec.row_letters = parse_letters(arg, value()?)?,
+        "--column-letters" => options.codec.column_letters = parse_letters(arg, value()?)?,
+        "--encode" => {
+          let seat = value()?;
+          let (row, column) = seat
+            .split_once(',')
+            .ok_or_else(|| Error::Usage(format!("{} expects ROW,COLUMN", arg)))?;
+          options.encode = Some((
+            parse_number(arg, row).map_err(Error::Usage)?,
+            parse_number(arg, column).map_err(Error::Usage)?,
+          ));
+        }
+        "--map" => options.map = true,
+        "--groups" => options.groups = parse_list(arg, value()?)?,
+        "--aisles" => options.aisles = Some(parse_list(arg, value()?)?),
+        _ => return Err(Error::Usage(format!("unknown argument {}", arg))),
+      }
+    }
+    options.codec.check().map_err(Error::Usage)?;
+    Ok(options)
+  }
+
+  // The columns an aisle runs before, one down the middle unless given
+  fn aisles(&self) -> Vec<u32> {
+    self
+      .aisles
+      .clone()
+      .unwrap_or_else(|| vec![self.codec.columns() / 2])
+  }
+}
+
+// Comma separated numbers, like 3,2,4
+fn parse_list<T: FromStr>(arg: &str, value: &str) -> Result<Vec<T>, Error> {
+  value
+    .split(',')
+    .filter(|n| !n.trim().is_empty())
+    .map(|n| parse_number(arg, n.trim()).map_err(Error::Usage))
+    .collect()
+}
+
+// Two letters, lower half first, like FB
+fn parse_letters(arg: &str, value: &str) -> Result<(char, char), Error> {
+  let mut letters = value.chars();
+  match (letters.next(), letters.next(), letters.next()) {
+    (Some(lower), Some(upper), None) => Ok((lower, upper)),
+    _ => Err(Error::Usage(format!(
+      "{} expects two letters, got [{}]",
+      arg, value
+    ))),
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] io::Error),
+  #[error("{}:{line}: {source}", .path.display())]
+  Pass {
+    path: PathBuf,
+    line: usize,
+    source: PassError,
+  },
+  #[error("{}: seat id {id} is on lines {first} and {second}", .path.display())]
+  Duplicate {
+    path: PathBuf,
+    id: u32,
+    first: usize,
+    second: usize,
+  },
+  #[error("{0}")]
+  Seat(String),
+  #[error("{0}")]
+  Usage(String),
+}
+
+// Every seat read from path, failing on the first invalid pass or repeated id
+fn read_seats<R: BufRead>(reader: R, path: &Path, codec: &Codec) -> Result<Vec<Seat>, Error> {
+  let mut seats = Vec::new();
+  let mut lines: HashMap<u32, usize> = HashMap::new();
+  for (i, line) in reader.lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let seat = codec.decode(&line).map_err(|source| Error::Pass {
+      path: path.to_path_buf(),
+      line: i + 1,
+      source,
+    })?;
+    if let Some(&first) = lines.get(&seat.id) {
+      return Err(Error::Duplicate {
+        path: path.to_path_buf(),
+        id: seat.id,
+        first,
+        second: i + 1,
+      });
+    }
+    lines.insert(seat.id, i + 1);
+    seats.push(seat);
+  }
+  Ok(seats)
+}
+
+pub fn day_5(options: &Options) -> Result<(), Error> {
+  if let Some((row, column)) = options.encode {
+    let seat = options.codec.seat_at(row, column).map_err(Error::Usage)?;
+    let pass = options.codec.encode(&seat).map_err(Error::Usage)?;
+    println!("{} is row {} column {}, id {}", pass, row, column, seat.id);
+    return Ok(());
+  }
+  let file = BufReader::new(File::open(&options.input)?);
+  let seats = read_seats(file, &options.input, &options.codec)?;
+  let occupancy = Occupancy::new(&options.codec, &seats);
+  if options.map {
+    let ours = occupancy.our_seat().ok();
+    print!(
+      "{}",
+      render_map(&occupancy, &options.codec, &options.aisles(), ours)
     );
+    return Ok(());
+  }
+  if !options.groups.is_empty() {
+    let mut allocator = Allocator::new(&options.codec, occupancy, &options.aisles());
+    for (i, &size) in options.groups.iter().enumerate() {
+      let allocation = match allocator.allocate(size) {
+        Ok(allocation) => allocation,
+        Err(e) => {
+          println!("group {} of {}: not seated, {}", i + 1, size, e);
+          continue;
+        }
+      };
+      let passes = allocation
+        .seats
+        .iter()
+        .map(|seat| options.codec.encode(seat))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::Seat)?;
+      println!(
+        "group {} of {}: {} ({})",
+        i + 1,
+        size,
+        passes.join(", "),
+        allocation.placement
+      );
+    }
+    return Ok(());
   }
+  day_5_puzzle_1(&seats);
+  day_5_puzzle_2(&occupancy)
+}
+
+fn day_5_puzzle_1(seats: &[Seat]) {
+  println!(
+    "Puzzle 1: max id is {}",
+    seats.iter().map(|s| s.id).max().unwrap_or_default()
+  );
+}
+
+// Which seat ids are taken, one bit per seat
+#[derive(Debug, Clone)]
+struct Occupancy {
+  bits: Vec<u64>,
+  capacity: u32,
+  columns: u32,
 }
 
-pub fn day_5_puzzle_2() {
-  let p = Path::new("inputs/day5.txt");
-  if let Ok(seats) = parse_singleline_sep::<Seat>(p) {
-    let seat_ids: Vec<u32> = seats.map(|s| s.id).map(|v| v).collect();
-    let first = seat_ids.iter().min().unwrap_or_else(|| &0);
-    let last = seat_ids.iter().max().unwrap_or_else(|| &0);
-    let mut our_seat: u32 = 0;
-    for v in *first..=*last {
-      if !seat_ids.contains(&v) {
-        our_seat = v;
+// Free seat ids by where they are: in the empty rows at the front, in the
+// rows from the first to the last with a taken seat, or in the empty rows
+// at the back
+#[derive(Debug, Default, Eq, PartialEq)]
+struct FreeSeats {
+  front: Vec<u32>,
+  interior: Vec<u32>,
+  back: Vec<u32>,
+}
+
+impl Occupancy {
+  fn new(codec: &Codec, seats: &[Seat]) -> Self {
+    let capacity = 1 << (codec.row_bits + codec.column_bits);
+    let mut occupancy = Occupancy {
+      bits: vec![0; (capacity as usize).div_ceil(64)],
+      capacity,
+      columns: codec.columns(),
+    };
+    for seat in seats {
+      occupancy.take(seat.id);
+    }
+    occupancy
+  }
+
+  fn take(&mut self, id: u32) {
+    self.bits[id as usize / 64] |= 1 << (id % 64);
+  }
+
+  fn is_taken(&self, id: u32) -> bool {
+    id < self.capacity && self.bits[id as usize / 64] >> (id % 64) & 1 == 1
+  }
+
+  fn free_seats(&self) -> FreeSeats {
+    let mut free = FreeSeats::default();
+    let first = (0..self.capacity).find(|&id| self.is_taken(id));
+    let last = (0..self.capacity).rev().find(|&id| self.is_taken(id));
+    let (first, last) = match (first, last) {
+      (Some(first), Some(last)) => (first, last),
+      _ => {
+        free.front = (0..self.capacity).collect();
+        return free;
+      }
+    };
+    // Whole rows, so a row with any taken seat is never at the front or back
+    let first = first / self.columns * self.columns;
+    let last = (last / self.columns + 1) * self.columns;
+    free.front = (0..first).collect();
+    free.interior = (first..last).filter(|&id| !self.is_taken(id)).collect();
+    free.back = (last..self.capacity).collect();
+    free
+  }
+
+  // The only free seat with both neighbours taken
+  fn our_seat(&self) -> Result<u32, String> {
+    let candidates: Vec<u32> = self
+      .free_seats()
+      .interior
+      .into_iter()
+      .filter(|&id| id > 0 && self.is_taken(id - 1) && self.is_taken(id + 1))
+      .collect();
+    match candidates.as_slice() {
+      [id] => Ok(*id),
+      [] => Err("no free seat has both neighbours taken".to_string()),
+      _ => {
+        let ids: Vec<String> = candidates.iter().map(|id| id.to_string()).collect();
+        Err(format!("several seats could be ours: {}", ids.join(", ")))
       }
     }
-    println!("Puzzle 2:{}", our_seat);
   }
 }
 
+fn day_5_puzzle_2(occupancy: &Occupancy) -> Result<(), Error> {
+  let free = occupancy.free_seats();
+  println!(
+    "{} free seats, {} at the front, {} in between and {} at the back",
+    free.front.len() + free.interior.len() + free.back.len(),
+    free.front.len(),
+    free.interior.len(),
+    free.back.len()
+  );
+  println!("Puzzle 2:{}", occupancy.our_seat().map_err(Error::Seat)?);
+  Ok(())
+}
+
+// How well a group was seated
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Placement {
+  // Side by side in one row, without an aisle in between
+  Together,
+  // Side by side in one row, but across an aisle
+  AcrossAisle,
+  // Spread over the free seats closest to each other
+  Split,
+}
+
+impl fmt::Display for Placement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Placement::Together => write!(f, "together"),
+      Placement::AcrossAisle => write!(f, "across an aisle"),
+      Placement::Split => write!(f, "split"),
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Allocation {
+  seats: Vec<Seat>,
+  placement: Placement,
+}
+
+// Seats groups front to back in the rows the boarding passes cover, or in
+// every row of an empty plane. Our own seat is never handed out.
+struct Allocator<'a> {
+  codec: &'a Codec,
+  occupancy: Occupancy,
+  // Column ranges between aisles
+  blocks: Vec<(u32, u32)>,
+  rows: (u32, u32),
+}
+
+impl<'a> Allocator<'a> {
+  fn new(codec: &'a Codec, mut occupancy: Occupancy, aisles: &[u32]) -> Self {
+    if let Ok(ours) = occupancy.our_seat() {
+      occupancy.take(ours);
+    }
+    let columns = codec.columns();
+    let mut edges: Vec<u32> = aisles
+      .iter()
+      .copied()
+      .filter(|&aisle| aisle > 0 && aisle < columns)
+      .collect();
+    edges.sort_unstable();
+    edges.dedup();
+    let starts = std::iter::once(0).chain(edges.iter().copied());
+    let ends = edges.iter().copied().chain(std::iter::once(columns));
+    let blocks = starts.zip(ends).collect();
+    let taken_rows = (0..occupancy.capacity)
+      .filter(|&id| occupancy.is_taken(id))
+      .map(|id| id / columns);
+    let rows = taken_rows.fold(None, |rows, row| match rows {
+      None => Some((row, row)),
+      Some((first, last)) => Some((first.min(row), last.max(row))),
+    });
+    Allocator {
+      codec,
+      occupancy,
+      blocks,
+      rows: rows.unwrap_or((0, (1 << codec.row_bits) - 1)),
+    }
+  }
+
+  fn allocate(&mut self, size: usize) -> Result<Allocation, String> {
+    if size == 0 {
+      return Err("a group needs at least one person".to_string());
+    }
+    let whole_row = [(0, self.codec.columns())];
+    let (ids, placement) = if let Some(ids) = self.run_in(&self.blocks.clone(), size) {
+      (ids, Placement::Together)
+    } else if let Some(ids) = self.run_in(&whole_row, size) {
+      (ids, Placement::AcrossAisle)
+    } else {
+      (self.closest(size)?, Placement::Split)
+    };
+    for &id in &ids {
+      self.occupancy.take(id);
+    }
+    Ok(Allocation {
+      seats: ids.iter().map(|&id| self.codec.seat(id)).collect(),
+      placement,
+    })
+  }
+
+  // The first row with size free seats next to each other inside a block
+  fn run_in(&self, blocks: &[(u32, u32)], size: usize) -> Option<Vec<u32>> {
+    for row in self.rows.0..=self.rows.1 {
+      for &(start, end) in blocks {
+        let mut run = Vec::new();
+        for column in start..end {
+          let id = self.codec.id(row, column);
+          if self.occupancy.is_taken(id) {
+            run.clear();
+            continue;
+          }
+          run.push(id);
+          if run.len() == size {
+            return Some(run);
+          }
+        }
+      }
+    }
+    None
+  }
+
+  // The free seats spanning the fewest ids, front first on ties
+  fn closest(&self, size: usize) -> Result<Vec<u32>, String> {
+    let first = self.codec.id(self.rows.0, 0);
+    let last = self.codec.id(self.rows.1, self.codec.columns() - 1);
+    let free: Vec<u32> = (first..=last)
+      .filter(|&id| !self.occupancy.is_taken(id))
+      .collect();
+    free
+      .windows(size)
+      .min_by_key(|window| window[size - 1] - window[0])
+      .map(|window| window.to_vec())
+      .ok_or_else(|| format!("{} free seats left for a group of {}", free.len(), size))
+  }
+}
+
+fn empty_rows(first: u32, last: u32) -> String {
+  match first == last {
+    true => format!("row {} empty\n", first),
+    false => format!("rows {}-{} empty\n", first, last),
+  }
+}
+
+// One line per row with # for taken seats, . for free ones and @ for ours,
+// an aisle down the middle, and the empty rows at the front and back
+// collapsed into one line each
+fn render_map(occupancy: &Occupancy, codec: &Codec, aisles: &[u32], ours: Option<u32>) -> String {
+  let rows = 1 << codec.row_bits;
+  let columns = codec.columns();
+  let occupied = |row: u32| (0..columns).any(|column| occupancy.is_taken(codec.id(row, column)));
+  let first = (0..rows).find(|&row| occupied(row));
+  let last = (0..rows).rev().find(|&row| occupied(row));
+  let width = (rows - 1).to_string().len();
+
+  let mut map = String::from("# taken, . free, @ ours\n");
+  let (first, last) = match (first, last) {
+    (Some(first), Some(last)) => (first, last),
+    _ => {
+      map.push_str(&empty_rows(0, rows - 1));
+      return map;
+    }
+  };
+  if first > 0 {
+    map.push_str(&empty_rows(0, first - 1));
+  }
+  for row in first..=last {
+    map.push_str(&format!("{:>width$} ", row, width = width));
+    for column in 0..columns {
+      if column > 0 && aisles.contains(&column) {
+        map.push(' ');
+      }
+      let id = codec.id(row, column);
+      map.push(match (occupancy.is_taken(id), Some(id) == ours) {
+        (_, true) => '@',
+        (true, false) => '#',
+        (false, false) => '.',
+      });
+    }
+    map.push('\n');
+  }
+  if last < rows - 1 {
+    map.push_str(&empty_rows(last + 1, rows - 1));
+  }
+  map
+}
+
 #[test]
 fn test_seat_from_str1() {
   let seat = Seat::from_str("BFFFBBFRRR").unwrap();
@@ -114,3 +676,246 @@ fn test_seat_from_str3() {
     }
   );
 }
+
+#[test]
+fn codec_round_trips() {
+  let codec = Codec::default();
+  for pass in &[
+    "BFFFBBFRRR",
+    "FFFBBBFRRR",
+    "BBFFBBFRLL",
+    "FFFFFFFLLL",
+    "BBBBBBBRRR",
+  ] {
+    let seat = codec.decode(pass).unwrap();
+    assert_eq!(&codec.encode(&seat).unwrap(), pass);
+  }
+  assert!(codec
+    .encode(&Seat {
+      row: 128,
+      column: 0,
+      id: 0
+    })
+    .is_err());
+  // The row is checked before the id could overflow
+  assert!(codec.seat_at(1_000_000_000, 0).is_err());
+
+  // A wider aircraft with 16 seats per row and its own letters
+  let wide = Codec {
+    row_bits: 6,
+    column_bits: 4,
+    row_letters: ('0', '1'),
+    column_letters: ('a', 'b'),
+  };
+  assert!(wide.check().is_ok());
+  assert!(Codec {
+    row_bits: u32::MAX,
+    column_bits: 1,
+    ..Codec::default()
+  }
+  .check()
+  .is_err());
+  let seat = wide.decode("000011abba").unwrap();
+  assert_eq!(
+    seat,
+    Seat {
+      row: 3,
+      column: 6,
+      id: 54
+    }
+  );
+  assert_eq!(wide.encode(&seat).unwrap(), "000011abba");
+  assert!(Codec {
+    row_letters: ('F', 'F'),
+    ..Codec::default()
+  }
+  .check()
+  .is_err());
+  let sized = |row_bits, column_bits| Codec {
+    row_bits,
+    column_bits,
+    ..Codec::default()
+  };
+  assert!(sized(12, 8).check().is_ok());
+  assert!(sized(16, 16).check().is_err());
+  assert!(sized(0, 3).check().is_err());
+}
+
+#[test]
+fn invalid_passes_name_the_position() {
+  let codec = Codec::default();
+  assert_eq!(
+    codec.decode("BFFFBBFRR"),
+    Err(PassError::Length {
+      pass: "BFFFBBFRR".to_string(),
+      found: 9,
+      expected: 10,
+    })
+  );
+  assert_eq!(
+    codec.decode("BFFFBB|RRR").unwrap_err().to_string(),
+    "[BFFFBB|RRR] has '|' at position 7, expected F or B"
+  );
+  assert_eq!(
+    codec.decode("BFFFBBFRLB").unwrap_err().to_string(),
+    "[BFFFBBFRLB] has 'B' at position 10, expected L or R"
+  );
+  assert!(Seat::from_str("BFFFBBFRR|").is_err());
+}
+
+#[test]
+fn duplicate_seats_are_rejected() {
+  let passes = io::Cursor::new("BFFFBBFRRR\nFFFBBBFRRR\n\nBFFFBBFRRR\n");
+  let error = read_seats(passes, Path::new("passes.txt"), &Codec::default()).unwrap_err();
+  assert_eq!(
+    error.to_string(),
+    "passes.txt: seat id 567 is on lines 1 and 4"
+  );
+  assert!(matches!(
+    error,
+    Error::Duplicate {
+      id: 567,
+      first: 1,
+      second: 4,
+      ..
+    }
+  ));
+  let passes = io::Cursor::new("BFFFBBFRRR\nBFFFBB|RRR\n");
+  let error = read_seats(passes, Path::new("passes.txt"), &Codec::default()).unwrap_err();
+  assert!(matches!(error, Error::Pass { line: 2, .. }));
+}
+
+#[test]
+fn map_collapses_empty_rows() {
+  let codec = Codec {
+    row_bits: 3,
+    column_bits: 2,
+    ..Codec::default()
+  };
+  let seats: Vec<Seat> = [
+    "FFBLL", "FFBLR", "FFBRR", "FBFLL", "FBFLR", "FBFRL", "FBFRR", "FBBLL",
+  ]
+  .iter()
+  .map(|pass| codec.decode(pass).unwrap())
+  .collect();
+  let occupancy = Occupancy::new(&codec, &seats);
+  assert_eq!(occupancy.our_seat(), Ok(6));
+  assert_eq!(
+    render_map(&occupancy, &codec, &[2], Some(6)),
+    "# taken, . free, @ ours
+row 0 empty
+1 ## @#
+2 ## ##
+3 #. ..
+rows 4-7 empty
+"
+  );
+  let empty = Occupancy::new(&codec, &[]);
+  assert_eq!(
+    render_map(&empty, &codec, &[2], None),
+    "# taken, . free, @ ours\nrows 0-7 empty\n"
+  );
+  // The same aisles as the allocator, none at the edges
+  assert_eq!(
+    render_map(&occupancy, &codec, &[0, 1, 3, 4], Some(6)),
+    "# taken, . free, @ ours
+row 0 empty
+1 # #@ #
+2 # ## #
+3 # .. .
+rows 4-7 empty
+"
+  );
+}
+
+#[test]
+fn free_seats_are_classified() {
+  let codec = Codec {
+    row_bits: 2,
+    column_bits: 2,
+    ..Codec::default()
+  };
+  let seats = |ids: &[u32]| -> Vec<Seat> {
+    ids
+      .iter()
+      .map(|&id| Seat {
+        row: id / 4,
+        column: id % 4,
+        id,
+      })
+      .collect()
+  };
+  let occupancy = Occupancy::new(&codec, &seats(&[2, 3, 4, 6, 7, 9, 10]));
+  assert_eq!(
+    occupancy.free_seats(),
+    FreeSeats {
+      front: vec![],
+      interior: vec![0, 1, 5, 8, 11],
+      back: vec![12, 13, 14, 15],
+    }
+  );
+  assert_eq!(
+    occupancy.our_seat(),
+    Err("several seats could be ours: 5, 8".to_string())
+  );
+  let occupancy = Occupancy::new(&codec, &seats(&[6, 7, 9, 10]));
+  assert_eq!(
+    occupancy.free_seats(),
+    FreeSeats {
+      front: vec![0, 1, 2, 3],
+      interior: vec![4, 5, 8, 11],
+      back: vec![12, 13, 14, 15],
+    }
+  );
+  let occupancy = Occupancy::new(&codec, &seats(&[2, 3, 4, 6, 7]));
+  assert_eq!(occupancy.our_seat(), Ok(5));
+  let occupancy = Occupancy::new(&codec, &seats(&[1, 2]));
+  assert_eq!(
+    occupancy.our_seat(),
+    Err("no free seat has both neighbours taken".to_string())
+  );
+  let occupancy = Occupancy::new(&codec, &seats(&[2, 3, 6, 7]));
+  assert!(occupancy.our_seat().is_err());
+  assert_eq!(Occupancy::new(&codec, &[]).free_seats().front.len(), 16);
+}
+
+#[test]
+fn allocator_seats_groups() {
+  let codec = Codec {
+    row_bits: 2,
+    column_bits: 2,
+    ..Codec::default()
+  };
+  // Row 0 has the middle two seats taken, rows 1 to 3 are free
+  let taken: Vec<Seat> = [1, 2].iter().map(|&id| codec.seat(id)).collect();
+  let mut allocator = Allocator::new(&codec, Occupancy::new(&codec, &taken), &[2]);
+  // Only row 0 is covered by boarding passes
+  assert_eq!(allocator.rows, (0, 0));
+  let allocation = allocator.allocate(1).unwrap();
+  assert_eq!(allocation.seats, vec![codec.seat(0)]);
+  assert_eq!(allocation.placement, Placement::Together);
+  assert!(allocator.allocate(2).is_err());
+
+  let mut allocator = Allocator::new(&codec, Occupancy::new(&codec, &[]), &[2]);
+  let ids =
+    |allocation: Allocation| -> Vec<u32> { allocation.seats.iter().map(|s| s.id).collect() };
+  assert_eq!(ids(allocator.allocate(2).unwrap()), vec![0, 1]);
+  assert_eq!(ids(allocator.allocate(1).unwrap()), vec![2]);
+  let allocation = allocator.allocate(3).unwrap();
+  assert_eq!(allocation.placement, Placement::AcrossAisle);
+  assert_eq!(ids(allocation), vec![4, 5, 6]);
+  // Left: 3, 7, and rows 2 and 3
+  assert_eq!(ids(allocator.allocate(4).unwrap()), vec![8, 9, 10, 11]);
+  assert_eq!(ids(allocator.allocate(4).unwrap()), vec![12, 13, 14, 15]);
+  let allocation = allocator.allocate(2).unwrap();
+  assert_eq!(allocation.placement, Placement::Split);
+  assert_eq!(ids(allocation), vec![3, 7]);
+  assert!(allocator.allocate(1).is_err());
+  assert!(allocator.allocate(0).is_err());
+
+  // Seat 5 is ours, so only 0 and 1 are left in the covered rows
+  let taken: Vec<Seat> = [2, 3, 4, 6, 7].iter().map(|&id| codec.seat(id)).collect();
+  let mut allocator = Allocator::new(&codec, Occupancy::new(&codec, &taken), &[2]);
+  assert_eq!(ids(allocator.allocate(2).unwrap()), vec![0, 1]);
+  assert!(allocator.allocate(1).is_err());
+}
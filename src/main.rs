@@ -8,44 +8,59 @@ mod day8;
 mod utils;
 
 use std::env::args;
+use std::fmt::Display;
+use std::process::exit;
 
 use day2::password_policy;
-use day3::{count_trees, count_trees_all_slopes};
-use day4::{count_valid_passports, count_valid_passports_values};
-use day5::{day_5_puzzle_1, day_5_puzzle_2};
+use day3::day_3;
+use day4::day_4;
+use day5::day_5;
 use day6::{day_6_puzzle_1, day_6_puzzle_2};
 use day7::{day_7_puzzle_1, day_7_puzzle_2};
 use day8::{day_8_puzzle_1, day_8_puzzle_2};
 
+// Reports why a day could not run and exits with a failure status
+fn fail(day: u32, e: impl Display) -> ! {
+    eprintln!("Day {} failed: {}", day, e);
+    exit(1)
+}
+
 fn main() {
-    let day = match args().nth(1) {
-        Some(input) => input,
+    let args: Vec<String> = args().collect();
+    let day = match args.get(1) {
+        Some(input) => input.clone(),
         None => "2".to_string(),
     };
+    let options = args.get(2..).unwrap_or_default();
 
     match day.as_str() {
         "2" => {
-            let (valid, valid_second, count) = password_policy();
-            println!("{} of {} passwords was valid.", valid, count);
+            let tally = day2::Options::from_args(options)
+                .and_then(|options| password_policy(&options))
+                .unwrap_or_else(|e| fail(2, e));
+            println!("{} of {} passwords was valid.", tally.valid, tally.count);
             println!(
                 "{} of {} passwords was valid according updated policy.",
-                valid_second, count
+                tally.valid_second, tally.count
             );
+            if tally.malformed > 0 {
+                println!("{} lines could not be parsed.", tally.malformed);
+            }
         }
         "3" => {
-            println!("Puzzle 1: {} trees were encountered", count_trees());
-            println!(
-                "Puzzle 2: {} product of all trees encounterd",
-                count_trees_all_slopes()
-            );
+            day3::Options::from_args(options)
+                .and_then(|options| day_3(&options))
+                .unwrap_or_else(|e| fail(3, e));
         }
         "4" => {
-            count_valid_passports();
-            count_valid_passports_values();
+            day4::Options::from_args(options)
+                .and_then(|options| day_4(&options))
+                .unwrap_or_else(|e| fail(4, e));
         }
         "5" => {
-            day_5_puzzle_1();
-            day_5_puzzle_2();
+            day5::Options::from_args(options)
+                .and_then(|options| day_5(&options))
+                .unwrap_or_else(|e| fail(5, e));
         }
         "6" => {
             day_6_puzzle_1();
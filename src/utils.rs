@@ -12,6 +12,18 @@ where
 }
 
 pub fn parse_newline_sep<T>(path: &Path) -> std::io::Result<impl '_ + Iterator<Item = T>>
+where
+  T: FromStr,
+  <T as FromStr>::Err: std::fmt::Display,
+{
+  Ok(parse_newline_sep_numbered(path)?.map(|(_, t)| t))
+}
+
+// Like parse_newline_sep, but also yields the line each record starts on.
+// A record that fails to parse is reported and skipped.
+pub fn parse_newline_sep_numbered<T>(
+  path: &Path,
+) -> std::io::Result<impl '_ + Iterator<Item = (usize, T)>>
 where
   T: FromStr,
   <T as FromStr>::Err: std::fmt::Display,
@@ -34,38 +46,40 @@ where
   }
 
   Ok(
-    std::iter::from_fn(move || {
+    std::iter::from_fn(move || loop {
       buf.clear();
+      let mut start = line + 1;
       while buf.is_empty() || !is_new_field(&buf) {
         line += 1;
         if reader.read_line(&mut buf).ok()? == 0 {
           break;
         }
+        if buf.trim().is_empty() {
+          start = line + 1;
+        }
       }
       if buf.is_empty() {
-        None
-      } else {
-        match T::from_str(&buf) {
-          Ok(t) => Some(t),
-          Err(e) => {
-            eprintln!(
-              "{}:{}: {}",
-              path
-                .file_name()
-                .expect("File::open() didn't early return before now; qed")
-                .to_string_lossy(),
-              line - 1,
-              e
-            );
-            None
-          }
-        }
+        return None;
+      }
+      match T::from_str(&buf) {
+        Ok(t) => return Some((start, t)),
+        Err(e) => eprintln!(
+          "{}:{}: {}",
+          path
+            .file_name()
+            .expect("File::open() didn't early return before now; qed")
+            .to_string_lossy(),
+          start,
+          e
+        ),
       }
     })
     .fuse(),
   )
 }
 
+// Not used by any day at the moment
+#[allow(dead_code)]
 pub fn parse_singleline_sep<T>(path: &Path) -> std::io::Result<impl '_ + Iterator<Item = T>>
 where
   T: FromStr,
@@ -138,7 +152,7 @@ where
       if buf.is_empty() {
         None
       } else {
-        match T::from_str(&buf.trim()) {
+        match T::from_str(buf.trim()) {
           Ok(t) => Some(t),
           Err(e) => {
             eprintln!(
@@ -159,3 +173,10 @@ where
   });
   Ok(iter)
 }
+
+// The value given for a command line argument as a number
+pub fn parse_number<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+  value
+    .parse()
+    .map_err(|_| format!("{} expects a number, got [{}]", arg, value))
+}